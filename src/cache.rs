@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+use bytes::Bytes;
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CACHE_CONTROL, COOKIE, EXPIRES, VARY,
+};
+
+/// Responses to `GET` requests kept in memory, each until the expiry its headers announced.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ResponseCache {
+    entries: HashMap<CacheKey, CachedResponse>,
+}
+
+/// What a cached response is looked up by: the url, the cookie jar of the session that sent the
+/// request and the credentials it carried, so one session's responses aren't served to another.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    url: String,
+    session: usize,
+    authorization: Option<HeaderValue>,
+    cookie: Option<HeaderValue>,
+}
+
+impl CacheKey {
+    /// `session` identifies the cookie jar in use, `headers` are the ones the request is sent with.
+    pub fn new(url: &str, session: usize, headers: &HeaderMap) -> Self {
+        CacheKey {
+            url: url.to_string(),
+            session,
+            authorization: headers.get(AUTHORIZATION).cloned(),
+            cookie: headers.get(COOKIE).cloned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CachedResponse {
+    pub status: u16,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    expires_at: Instant,
+    // the request headers named by `Vary`, with the values the response was fetched with
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+}
+
+impl ResponseCache {
+    /// Returns the response cached for `key`, unless it has expired or the request `headers`
+    /// differ from the ones it was fetched with in a header its `Vary` names.
+    pub fn get(&mut self, key: &CacheKey, headers: &HeaderMap) -> Option<&CachedResponse> {
+        let expired = self.entries.get(key)?.expires_at <= Instant::now();
+        if expired {
+            self.entries.remove(key);
+            return None;
+        }
+
+        self.entries.get(key).filter(|cached| {
+            cached
+                .vary
+                .iter()
+                .all(|(name, value)| headers.get(name) == value.as_ref())
+        })
+    }
+
+    /// Caches the response to a request sent with `request_headers` for as long as its headers
+    /// allow. Responses without freshness information, marked `no-store`, `private` or
+    /// `no-cache`, or varying on `*` are not cached.
+    pub fn insert(
+        &mut self,
+        key: CacheKey,
+        request_headers: &HeaderMap,
+        status: u16,
+        headers: &HeaderMap,
+        body: Bytes,
+    ) {
+        let expires_at = match freshness(headers, SystemTime::now())
+            .and_then(|ttl| Instant::now().checked_add(ttl))
+        {
+            Some(expires_at) => expires_at,
+            None => return,
+        };
+        let vary = match vary(headers, request_headers) {
+            Some(vary) => vary,
+            None => return,
+        };
+
+        self.entries.insert(
+            key,
+            CachedResponse {
+                status,
+                headers: headers.clone(),
+                body,
+                expires_at,
+                vary,
+            },
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+// the request headers named by the response's `Vary` with their values, None for `Vary: *`
+fn vary(
+    headers: &HeaderMap,
+    request_headers: &HeaderMap,
+) -> Option<Vec<(HeaderName, Option<HeaderValue>)>> {
+    let mut vary = Vec::new();
+    let names = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+    for name in names {
+        if name == "*" {
+            return None;
+        }
+        if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+            let value = request_headers.get(&name).cloned();
+            vary.push((name, value));
+        }
+    }
+    Some(vary)
+}
+
+// the longest lifetime a response is given, as RFC 9111 caps delta-seconds at 2^31
+const MAX_FRESHNESS: Duration = Duration::from_secs(1 << 31);
+
+// how long a response stays fresh: `max-age` wins over `Expires`, and a zero lifetime is None
+fn freshness(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let cache_control = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    let uncacheable = ["no-store", "private", "no-cache"];
+    if cache_control
+        .iter()
+        .any(|directive| uncacheable.contains(&directive.as_str()))
+    {
+        return None;
+    }
+
+    let max_age = cache_control
+        .iter()
+        .find_map(|directive| directive.strip_prefix("max-age="))
+        .and_then(|secs| secs.trim_matches('"').parse::<u64>().ok());
+    let ttl = match max_age {
+        Some(secs) => Duration::from_secs(secs),
+        None => {
+            let expires = headers.get(EXPIRES)?.to_str().ok()?;
+            let expires = httpdate::parse_http_date(expires).ok()?;
+            expires.duration_since(now).ok()?
+        }
+    };
+
+    (!ttl.is_zero()).then_some(ttl.min(MAX_FRESHNESS))
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                reqwest::header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn freshness_should_follow_cache_control_and_expires() {
+        let now = SystemTime::now();
+        let in_a_minute = httpdate::fmt_http_date(now + Duration::from_secs(60));
+        let a_minute_ago = httpdate::fmt_http_date(now - Duration::from_secs(60));
+
+        let fresh = |pairs: &[(&str, &str)]| freshness(&headers(pairs), now);
+
+        assert_eq!(
+            fresh(&[("Cache-Control", "public, max-age=300")]),
+            Some(Duration::from_secs(300))
+        );
+        assert_eq!(fresh(&[("Cache-Control", "max-age=0")]), None);
+        assert_eq!(fresh(&[("Cache-Control", "no-store, max-age=300")]), None);
+        assert_eq!(fresh(&[("Cache-Control", "private")]), None);
+        assert_eq!(
+            fresh(&[("Cache-Control", "max-age=5"), ("Expires", &in_a_minute)]),
+            Some(Duration::from_secs(5))
+        );
+        assert!(fresh(&[("Expires", &in_a_minute)]).unwrap() > Duration::from_secs(58));
+        assert_eq!(fresh(&[("Expires", &a_minute_ago)]), None);
+        assert_eq!(fresh(&[("Expires", "0")]), None);
+        assert_eq!(fresh(&[]), None);
+        assert_eq!(
+            fresh(&[("Cache-Control", "max-age=18446744073709551615")]),
+            Some(MAX_FRESHNESS)
+        );
+    }
+
+    #[test]
+    fn cache_should_only_return_fresh_responses() {
+        let mut cache = ResponseCache::default();
+        let none = HeaderMap::new();
+        let key = CacheKey::new("http://example.com/", 0, &none);
+
+        cache.insert(
+            key.clone(),
+            &none,
+            200,
+            &headers(&[("Cache-Control", "no-store")]),
+            "a".into(),
+        );
+        assert!(cache.get(&key, &none).is_none());
+
+        cache.insert(
+            key.clone(),
+            &none,
+            200,
+            &headers(&[("Cache-Control", "max-age=60")]),
+            "b".into(),
+        );
+        assert_eq!(cache.get(&key, &none).unwrap().body, "b");
+
+        cache.entries.get_mut(&key).unwrap().expires_at = Instant::now();
+        assert!(cache.get(&key, &none).is_none());
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn cache_should_keep_a_response_with_a_huge_max_age() {
+        let mut cache = ResponseCache::default();
+        let none = HeaderMap::new();
+        let key = CacheKey::new("http://example.com/", 0, &none);
+
+        let forever = headers(&[("Cache-Control", "max-age=18446744073709551615")]);
+        cache.insert(key.clone(), &none, 200, &forever, "a".into());
+        assert_eq!(cache.get(&key, &none).unwrap().body, "a");
+    }
+
+    #[test]
+    fn cache_should_separate_sessions_and_credentials() {
+        let mut cache = ResponseCache::default();
+        let url = "http://example.com/";
+        let alice = headers(&[("Authorization", "Bearer alice")]);
+        let bob = headers(&[("Authorization", "Bearer bob")]);
+        let fresh = headers(&[("Cache-Control", "max-age=60")]);
+
+        cache.insert(
+            CacheKey::new(url, 1, &alice),
+            &alice,
+            200,
+            &fresh,
+            "a".into(),
+        );
+
+        assert!(cache.get(&CacheKey::new(url, 1, &alice), &alice).is_some());
+        assert!(cache.get(&CacheKey::new(url, 1, &bob), &bob).is_none());
+        assert!(cache.get(&CacheKey::new(url, 2, &alice), &alice).is_none());
+    }
+
+    #[test]
+    fn cache_should_honor_vary() {
+        let mut cache = ResponseCache::default();
+        let none = HeaderMap::new();
+        let key = CacheKey::new("http://example.com/", 0, &none);
+        let english = headers(&[("Accept-Language", "en")]);
+        let french = headers(&[("Accept-Language", "fr")]);
+
+        let response = headers(&[("Cache-Control", "max-age=60"), ("Vary", "Accept-Language")]);
+        cache.insert(key.clone(), &english, 200, &response, "hello".into());
+        assert!(cache.get(&key, &english).is_some());
+        assert!(cache.get(&key, &french).is_none());
+        assert!(cache.get(&key, &none).is_none());
+
+        cache.clear();
+        let response = headers(&[("Cache-Control", "max-age=60"), ("Vary", "*")]);
+        cache.insert(key.clone(), &english, 200, &response, "hello".into());
+        assert!(cache.get(&key, &english).is_none());
+    }
+}
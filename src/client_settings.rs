@@ -1,10 +1,76 @@
-use reqwest::Proxy;
+use reqwest::{Client, Proxy};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+#[cfg(feature = "uds")]
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// What to do when the downloaded body size doesn't match the response's `Content-Length`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentLengthCheck {
+    /// Skips the check.
+    Ignore,
+    /// Passes `StepError::ContentLengthMismatch` to the worker's default error handler, if any,
+    /// and carries on. Nothing is reported when no handler is set.
+    Warn,
+    /// Fails the step with `StepError::ContentLengthMismatch`.
+    Error,
+}
+
+/// How often `ClientSettings::use_random_browser_ua` picks a new user agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserAgentRotation {
+    /// Picks one user agent and keeps it for every request.
+    Once,
+    /// Picks a user agent for each request.
+    PerRequest,
+}
+
+/// Realistic desktop browser user agents `ClientSettings::use_random_browser_ua` picks from.
+pub const BROWSER_USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:125.0) Gecko/20100101 Firefox/125.0",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 14.4; rv:125.0) Gecko/20100101 Firefox/125.0",
+    "Mozilla/5.0 (X11; Linux x86_64; rv:125.0) Gecko/20100101 Firefox/125.0",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 14_4_1) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4.1 Safari/605.1.15",
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4.1 Mobile/15E148 Safari/604.1",
+];
+
+/// A proxy in the rotation, see `ClientSettings::add_rotating_proxy`.
+#[derive(Debug, Clone)]
+struct PooledProxy {
+    url: String,
+    proxy: Proxy,
+    failures: u32,
+    disabled: bool,
+}
 
 #[derive(Clone)]
 pub struct ClientSettings {
     proxy: Option<Proxy>,
+    /// The url of `proxy`, credentials included, when it came from the rotation.
+    proxy_url: Option<String>,
     user_agent: Option<String>,
+    /// The rotation and the user agent picked when it was enabled.
+    random_user_agent: Option<(UserAgentRotation, String)>,
     gzip: bool,
+    #[cfg(feature = "zstd")]
+    zstd: bool,
+    max_decompressed_bytes: Option<usize>,
+    content_length_check: ContentLengthCheck,
+    text_content_type: Option<String>,
+    redirects_as_success: bool,
+    empty_status_codes_means_none: bool,
+    max_retry_after: Duration,
+    host_overrides: Vec<(String, SocketAddr)>,
+    proxy_pool: Vec<PooledProxy>,
+    proxy_cursor: usize,
+    proxy_check_url: String,
+    #[cfg(feature = "uds")]
+    unix_socket: Option<PathBuf>,
 }
 
 impl Default for ClientSettings {
@@ -13,17 +79,41 @@ impl Default for ClientSettings {
     }
 }
 
+// `RandomState` is seeded randomly, which is random enough to pick a user agent without a
+// dependency
+fn random_browser_ua() -> String {
+    let random = RandomState::new().build_hasher().finish() as usize;
+    BROWSER_USER_AGENTS[random % BROWSER_USER_AGENTS.len()].to_string()
+}
+
 impl ClientSettings {
     pub fn new() -> Self {
         Self {
             proxy: None,
+            proxy_url: None,
             user_agent: None,
+            random_user_agent: None,
             gzip: true,
+            #[cfg(feature = "zstd")]
+            zstd: false,
+            max_decompressed_bytes: None,
+            content_length_check: ContentLengthCheck::Warn,
+            text_content_type: Some(String::from("text/plain; charset=utf-8")),
+            redirects_as_success: false,
+            empty_status_codes_means_none: true,
+            max_retry_after: Duration::from_secs(60),
+            host_overrides: Vec::new(),
+            proxy_pool: Vec::new(),
+            proxy_cursor: 0,
+            proxy_check_url: String::from("http://example.com/"),
+            #[cfg(feature = "uds")]
+            unix_socket: None,
         }
     }
 
     pub fn set_proxy(&mut self, proxy: Option<Proxy>) -> &mut Self {
         self.proxy = proxy;
+        self.proxy_url = None;
         self
     }
 
@@ -31,6 +121,115 @@ impl ClientSettings {
         self.proxy.as_ref()
     }
 
+    // the full url of the proxy in use, known only for proxies of the rotation
+    pub(crate) fn proxy_url(&self) -> Option<&str> {
+        self.proxy_url.as_deref()
+    }
+
+    /// Adds a proxy to the rotation used by requests that don't set their own proxy.
+    pub fn add_rotating_proxy(&mut self, url: &str) -> Result<&mut Self, reqwest::Error> {
+        let proxy = Proxy::all(url)?;
+        self.proxy_pool.push(PooledProxy {
+            url: url.to_string(),
+            proxy,
+            failures: 0,
+            disabled: false,
+        });
+        Ok(self)
+    }
+
+    /// Sets the url `check_proxies` fetches through each proxy. Defaults to `http://example.com/`.
+    pub fn set_proxy_check_url(&mut self, url: &str) -> &mut Self {
+        self.proxy_check_url = url.to_string();
+        self
+    }
+
+    /// Fetches the proxy check url through every rotating proxy. Proxies that fail are disabled
+    /// and left out of the rotation until a later check succeeds. Call it periodically to keep
+    /// the pool healthy.
+    pub async fn check_proxies(&mut self) {
+        const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+        for pooled in &mut self.proxy_pool {
+            let healthy = match Client::builder()
+                .proxy(pooled.proxy.clone())
+                .timeout(CHECK_TIMEOUT)
+                .build()
+            {
+                Ok(client) => client
+                    .get(&self.proxy_check_url)
+                    .send()
+                    .await
+                    .is_ok_and(|res| res.status().is_success()),
+                Err(_) => false,
+            };
+
+            if !healthy {
+                pooled.failures += 1;
+            }
+            pooled.disabled = !healthy;
+        }
+    }
+
+    // forgets the health checks of the rotation, re-enabling every proxy, see `Worker::reset`
+    pub(crate) fn reset_proxy_health(&mut self) {
+        for pooled in &mut self.proxy_pool {
+            pooled.failures = 0;
+            pooled.disabled = false;
+        }
+        self.proxy_cursor = 0;
+    }
+
+    /// Returns the next enabled proxy of the rotation, if any.
+    pub fn next_rotating_proxy(&mut self) -> Option<Proxy> {
+        let pooled = self.next_pooled()?;
+        Some(self.proxy_pool[pooled].proxy.clone())
+    }
+
+    /// Switches to the next enabled proxy of the rotation, or to no proxy without one.
+    pub(crate) fn use_next_rotating_proxy(&mut self) {
+        match self.next_pooled() {
+            Some(pooled) => {
+                let pooled = &self.proxy_pool[pooled];
+                self.proxy = Some(pooled.proxy.clone());
+                self.proxy_url = Some(pooled.url.clone());
+            }
+            None => {
+                self.set_proxy(None);
+            }
+        }
+    }
+
+    // the index of the next enabled proxy of the rotation, moving the cursor past it
+    fn next_pooled(&mut self) -> Option<usize> {
+        let len = self.proxy_pool.len();
+        for _ in 0..len {
+            let index = self.proxy_cursor % len;
+            self.proxy_cursor = (self.proxy_cursor + 1) % len;
+            if !self.proxy_pool[index].disabled {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    /// Returns the urls of the rotating proxies that aren't disabled.
+    pub fn active_proxies(&self) -> Vec<String> {
+        self.proxy_pool
+            .iter()
+            .filter(|pooled| !pooled.disabled)
+            .map(|pooled| pooled.url.clone())
+            .collect()
+    }
+
+    /// Returns the number of failed health checks of a rotating proxy.
+    pub fn proxy_failures(&self, url: &str) -> Option<u32> {
+        self.proxy_pool
+            .iter()
+            .find(|pooled| pooled.url == url)
+            .map(|pooled| pooled.failures)
+    }
+
     pub fn set_user_agent(&mut self, user_agent: Option<String>) -> &mut Self {
         self.user_agent = user_agent;
         self
@@ -40,6 +239,25 @@ impl ClientSettings {
         self.user_agent.as_ref()
     }
 
+    /// Sends a user agent picked at random from `BROWSER_USER_AGENTS` with requests that don't
+    /// set their own, either the same one every time or a new one per request.
+    pub fn use_random_browser_ua(&mut self, rotation: UserAgentRotation) -> &mut Self {
+        let picked = random_browser_ua();
+        self.user_agent = Some(picked.clone());
+        self.random_user_agent = Some((rotation, picked));
+        self
+    }
+
+    /// Returns the user agent for a request that doesn't set one: a freshly picked one when
+    /// rotating per request, the one picked before otherwise.
+    pub(crate) fn next_user_agent(&mut self) -> Option<String> {
+        match &self.random_user_agent {
+            Some((UserAgentRotation::PerRequest, _)) => Some(random_browser_ua()),
+            Some((UserAgentRotation::Once, picked)) => Some(picked.clone()),
+            None => None,
+        }
+    }
+
     pub fn set_compression(&mut self, gzip: bool) -> &mut Self {
         self.gzip = gzip;
         self
@@ -58,4 +276,113 @@ impl ClientSettings {
     pub fn is_compressed(&self) -> bool {
         self.gzip
     }
+
+    /// Asks for zstd-compressed responses and decompresses them, as well as bodies starting with
+    /// the zstd magic bytes without a `Content-Encoding`. reqwest 0.11 can't decode zstd, so this
+    /// only applies to steps run by a worker, which decompress bodies themselves. Requests with
+    /// compression turned off, like the streamed ones, neither ask for nor decode zstd.
+    #[cfg(feature = "zstd")]
+    pub fn set_zstd(&mut self, zstd: bool) -> &mut Self {
+        self.zstd = zstd;
+        self
+    }
+
+    #[cfg(feature = "zstd")]
+    pub fn is_zstd(&self) -> bool {
+        self.zstd
+    }
+
+    /// Caps the size of a response body after decompression, protecting against zip bombs.
+    /// Bodies growing past the limit fail with `StepError::DecompressionLimitExceeded`.
+    pub fn set_max_decompressed_bytes(&mut self, max: Option<usize>) -> &mut Self {
+        self.max_decompressed_bytes = max;
+        self
+    }
+
+    pub fn max_decompressed_bytes(&self) -> Option<usize> {
+        self.max_decompressed_bytes
+    }
+
+    /// Sets how a body whose size doesn't match `Content-Length` is handled. Defaults to `Warn`;
+    /// pass `Ignore` to turn the check off.
+    pub fn set_content_length_check(&mut self, check: ContentLengthCheck) -> &mut Self {
+        self.content_length_check = check;
+        self
+    }
+
+    pub fn content_length_check(&self) -> ContentLengthCheck {
+        self.content_length_check
+    }
+
+    /// Sets the `Content-Type` sent with `MimicBody::Text` bodies whose request doesn't set one.
+    /// Defaults to `text/plain; charset=utf-8`, `None` sends text bodies without a content type.
+    pub fn set_text_content_type(&mut self, content_type: Option<String>) -> &mut Self {
+        self.text_content_type = content_type;
+        self
+    }
+
+    pub fn text_content_type(&self) -> Option<&String> {
+        self.text_content_type.as_ref()
+    }
+
+    /// Counts 3xx responses as success for requests that don't list their status codes, e.g.
+    /// when a redirect that isn't followed is the expected answer. Off by default, which only
+    /// accepts 200 to 299.
+    pub fn set_redirects_as_success(&mut self, accept: bool) -> &mut Self {
+        self.redirects_as_success = accept;
+        self
+    }
+
+    pub fn redirects_as_success(&self) -> bool {
+        self.redirects_as_success
+    }
+
+    /// Caps how long a retry waits for a `Retry-After` header, so a server asking for hours or
+    /// a far-future date can't stall the step. Defaults to 60 seconds.
+    pub fn set_max_retry_after(&mut self, max: Duration) -> &mut Self {
+        self.max_retry_after = max;
+        self
+    }
+
+    pub fn max_retry_after(&self) -> Duration {
+        self.max_retry_after
+    }
+
+    /// Decides what a request with an empty `status_codes` list accepts. `true`, the default,
+    /// treats it like a request without a list: any 2xx status is a success. `false` takes the
+    /// list literally, so no status is a success (`Request::accept_any_2xx` still applies).
+    pub fn set_empty_status_codes_means_none(&mut self, means_none: bool) -> &mut Self {
+        self.empty_status_codes_means_none = means_none;
+        self
+    }
+
+    pub fn empty_status_codes_means_none(&self) -> bool {
+        self.empty_status_codes_means_none
+    }
+
+    /// Resolves `host` to `addr` instead of asking DNS, like an `/etc/hosts` entry.
+    /// The port of `addr` is ignored, the url's port is used instead.
+    /// Adding an override for a host that already has one replaces it.
+    pub fn add_host_override(&mut self, host: &str, addr: SocketAddr) -> &mut Self {
+        self.host_overrides.retain(|(h, _)| h != host);
+        self.host_overrides.push((host.to_string(), addr));
+        self
+    }
+
+    pub fn host_overrides(&self) -> &[(String, SocketAddr)] {
+        &self.host_overrides
+    }
+
+    /// Sends every request over the Unix domain socket at `path` instead of TCP.
+    /// The url's host is then only a placeholder, e.g. `http://localhost/v1.43/info`.
+    #[cfg(feature = "uds")]
+    pub fn set_unix_socket(&mut self, path: Option<PathBuf>) -> &mut Self {
+        self.unix_socket = path;
+        self
+    }
+
+    #[cfg(feature = "uds")]
+    pub fn unix_socket(&self) -> Option<&PathBuf> {
+        self.unix_socket.as_ref()
+    }
 }
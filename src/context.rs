@@ -1,10 +1,26 @@
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::error::Error;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use encoding_rs::{Encoding, UTF_8};
-use reqwest::RequestBuilder;
+use percent_encoding::percent_decode_str;
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE,
+};
+use reqwest::{RequestBuilder, Version};
+#[cfg(any(feature = "json", feature = "msgpack", feature = "cbor"))]
 use serde::de::DeserializeOwned;
 
-use crate::{HttpRequester, Request};
+use crate::limits::Limits;
+use crate::{ClientSettings, HttpRequester, Request, SentRequest, StepError};
 
 /// The context for the bots current step's execution.
 /// This is passed to the step's `on_success` and `on_error` methods.
@@ -17,6 +33,12 @@ pub struct Context {
     http_requester: HttpRequester,
     /// The request builder from reqwest.
     request_builder: Option<RequestBuilder>,
+    /// The request as sent, with the client's default headers.
+    sent_request: Option<SentRequest>,
+    /// The status code of the response.
+    status_code: Option<u16>,
+    /// The headers of the response.
+    response_headers: Option<HeaderMap>,
     /// The response from the request.
     response_body: Option<bytes::Bytes>,
     /// The next step to be executed.
@@ -25,8 +47,29 @@ pub struct Context {
     status_codes: Option<Vec<u16>>,
     /// The time elapsed in milliseconds for the request.
     time_elapsed: u64,
+    /// Whether the response was a `304 Not Modified` to a conditional request.
+    not_modified: bool,
+    /// The size of the response body as received, before decompression.
+    transfer_size: Option<u64>,
+    /// The HTTP version the response was served with.
+    http_version: Option<Version>,
+    /// The error of the last step that failed, kept until another one fails.
+    last_error: Option<StepError>,
+    /// The last `ETag` received, kept across steps for the next conditional request.
+    etag: Option<String>,
+    /// Values steps share with each other, keyed by name.
+    state: HashMap<String, Box<dyn Any + Send + Sync>>,
+    /// Handles the errors of steps that don't implement `on_error`.
+    default_error_handler: Option<ErrorHandler>,
+    /// Whether the default error handler sees every error instead of only the unhandled ones.
+    always_default_error_handler: bool,
+    /// The concurrency limits every request sent waits on.
+    limits: Limits,
 }
 
+/// Handles step errors, given the name of the step that failed. See `Worker::set_default_error_handler`.
+pub type ErrorHandler = Arc<dyn Fn(&str, &StepError) + Send + Sync>;
+
 impl Default for Context {
     fn default() -> Self {
         Context::new()
@@ -37,17 +80,31 @@ impl Context {
     pub fn new() -> Self {
         let request = Request::default();
         let http_requester = HttpRequester::new();
-        let request_builder = http_requester.build_reqwest(request.clone()).unwrap();
+        let request_builder = http_requester
+            .build_reqwest_undecoded(request.clone())
+            .unwrap();
 
         Context {
             request,
             current_step: None,
             http_requester,
             request_builder: Some(request_builder),
+            sent_request: None,
+            status_code: None,
+            response_headers: None,
             response_body: None,
             next_step: None,
             status_codes: None,
             time_elapsed: 0,
+            not_modified: false,
+            transfer_size: None,
+            http_version: None,
+            last_error: None,
+            etag: None,
+            state: HashMap::new(),
+            default_error_handler: None,
+            always_default_error_handler: false,
+            limits: Limits::default(),
         }
     }
 
@@ -90,6 +147,120 @@ impl Context {
     pub fn get_time_elapsed_as_string(&self) -> String {
         format!("{} ms", self.time_elapsed)
     }
+
+    /// Records the error of a failed step, right before its error hooks run.
+    pub(crate) fn set_last_error(&mut self, error: StepError) {
+        self.last_error = Some(error);
+    }
+
+    /// Gets the error of the last step that failed, which successful steps leave in place, e.g.
+    /// to report it from a teardown hook.
+    pub fn last_error(&self) -> Option<&StepError> {
+        self.last_error.as_ref()
+    }
+
+    /// Sets the size of the response body as received, before decompression.
+    pub(crate) fn set_transfer_size(&mut self, bytes: u64) {
+        self.transfer_size = Some(bytes);
+    }
+
+    /// Gets the size in bytes of the response body as it was received, before decompression.
+    pub fn transfer_size(&self) -> Option<u64> {
+        self.transfer_size
+    }
+
+    /// Sets the HTTP version the response was served with.
+    pub(crate) fn set_http_version(&mut self, version: Version) {
+        self.http_version = Some(version);
+    }
+
+    /// Gets the HTTP version the response was served with, e.g. to confirm a protocol was used.
+    /// `None` without a response read from the network.
+    pub fn http_version(&self) -> Option<Version> {
+        self.http_version
+    }
+
+    /// Gets the HTTP version the response was served with as text, e.g. `"HTTP/1.1"` or
+    /// `"HTTP/2.0"`.
+    pub fn response_version(&self) -> Option<String> {
+        self.http_version.map(|version| format!("{:?}", version))
+    }
+
+    /// Gets how many times larger the response body is than what was received, e.g. `4.0` for a
+    /// body gzipped to a quarter of its size and `1.0` for an uncompressed one.
+    /// `None` without a response read from the network or when nothing was received.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        let transfer_size = self.transfer_size.filter(|size| *size > 0)?;
+        let body_size = self.response_body.as_ref()?.len();
+        Some(body_size as f64 / transfer_size as f64)
+    }
+
+    /// Gets the time elapsed as a `Duration`, e.g. to compare it against a timeout or an SLA.
+    pub fn time_elapsed_duration(&self) -> Duration {
+        Duration::from_millis(self.time_elapsed)
+    }
+
+    /// Gets the client settings used to build requests.
+    pub fn client_settings(&self) -> &ClientSettings {
+        &self.http_requester.settings
+    }
+
+    /// Gets the client settings mutably, to configure how the following requests are built.
+    pub fn client_settings_mut(&mut self) -> &mut ClientSettings {
+        &mut self.http_requester.settings
+    }
+
+    /// Gets the HTTP requester which manages the cookie store and client settings.
+    pub(crate) fn http_requester(&self) -> &HttpRequester {
+        &self.http_requester
+    }
+
+    pub(crate) fn http_requester_mut(&mut self) -> &mut HttpRequester {
+        &mut self.http_requester
+    }
+
+    pub(crate) fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub(crate) fn limits_mut(&mut self) -> &mut Limits {
+        &mut self.limits
+    }
+
+    /// Replaces the HTTP requester, e.g. with one created by `HttpRequester::from_client`.
+    pub fn set_http_requester(&mut self, http_requester: HttpRequester) {
+        self.http_requester = http_requester;
+    }
+
+    pub(crate) fn set_default_error_handler(&mut self, handler: Option<ErrorHandler>) {
+        self.default_error_handler = handler;
+    }
+
+    pub(crate) fn default_error_handler(&self) -> Option<ErrorHandler> {
+        self.default_error_handler.clone()
+    }
+
+    pub(crate) fn set_always_default_error_handler(&mut self, always: bool) {
+        self.always_default_error_handler = always;
+    }
+
+    pub(crate) fn always_default_error_handler(&self) -> bool {
+        self.always_default_error_handler
+    }
+
+    /// Passes an error the step didn't handle to the worker's default error handler, if any.
+    /// This is what `Stepable::on_error` does unless a step implements it.
+    pub fn handle_default_error(&self, step: &str, err: &StepError) {
+        // in always mode the worker calls the handler itself after every on_error
+        if self.always_default_error_handler {
+            return;
+        }
+
+        if let Some(handler) = &self.default_error_handler {
+            handler(step, err);
+        }
+    }
+
     /// Sets the request builder.
     pub fn set_request_builder(&mut self, req_builder: RequestBuilder) {
         self.request_builder = Some(req_builder);
@@ -100,12 +271,17 @@ impl Context {
         self.request_builder.take()
     }
 
+    /// Gets the request that is currently being executed.
+    pub fn get_request(&self) -> &Request {
+        &self.request
+    }
+
     pub fn get_url(&self) -> String {
         self.request.url().clone()
     }
 
     pub fn get_method(&self) -> String {
-        self.request.method().to_string().clone()
+        self.request.method_ref().to_string()
     }
 
     pub fn get_status_codes(&self) -> Option<Vec<u16>> {
@@ -116,6 +292,256 @@ impl Context {
         self.status_codes = Some(status_codes);
     }
 
+    /// Sets the response status code.
+    pub fn set_status_code(&mut self, status_code: u16) {
+        self.status_code = Some(status_code);
+    }
+
+    /// Gets the response status code, if a response has been received for the current step.
+    pub fn get_status_code(&self) -> Option<u16> {
+        self.status_code
+    }
+
+    /// Clears the status code, headers and timing of the previous response
+    /// so they aren't mistaken for the current step's. The body is kept until it is replaced.
+    pub(crate) fn clear_response(&mut self) {
+        self.status_code = None;
+        self.response_headers = None;
+        self.time_elapsed = 0;
+        self.not_modified = false;
+        self.transfer_size = None;
+        self.http_version = None;
+    }
+
+    /// Forgets the current request, the last response, errors and state, and empties the cookie
+    /// store, keeping the requester with its settings and any injected client.
+    pub(crate) fn reset(&mut self) {
+        self.clear_response();
+        self.request = Request::default();
+        self.request_builder = self
+            .http_requester
+            .build_reqwest_undecoded(self.request.clone())
+            .ok();
+        self.current_step = None;
+        self.sent_request = None;
+        self.response_body = None;
+        self.next_step = None;
+        self.status_codes = None;
+        self.last_error = None;
+        self.etag = None;
+        self.state.clear();
+        self.http_requester.cookies().clear();
+        self.client_settings_mut().reset_proxy_health();
+    }
+
+    /// Gets the request of the current step as it is sent, with the headers the client adds by
+    /// default, to debug differences between the intended and the actual request.
+    /// `None` for multipart requests without a fixed boundary.
+    pub fn sent_request(&self) -> Option<&SentRequest> {
+        self.sent_request.as_ref()
+    }
+
+    /// Sets the response headers.
+    pub fn set_response_headers(&mut self, headers: HeaderMap) {
+        self.response_headers = Some(headers);
+    }
+
+    /// Gets the response headers, if a response has been received.
+    pub fn get_response_headers(&self) -> Option<&HeaderMap> {
+        self.response_headers.as_ref()
+    }
+
+    /// Sets whether the response was a `304 Not Modified` to a conditional request.
+    pub fn set_not_modified(&mut self, not_modified: bool) {
+        self.not_modified = not_modified;
+    }
+
+    /// Returns true when a request sent `If-None-Match` and the resource hasn't changed.
+    /// The body is empty in that case.
+    pub fn not_modified(&self) -> bool {
+        self.not_modified
+    }
+
+    /// Sets the `ETag` to send with the next conditional request.
+    pub fn set_etag(&mut self, etag: String) {
+        self.etag = Some(etag);
+    }
+
+    /// Gets the last `ETag` received, e.g. for `Request::with_if_none_match` on the next poll.
+    pub fn etag(&self) -> Option<String> {
+        self.etag.clone()
+    }
+
+    /// Gets the `Content-Length` the server announced for the response body.
+    pub fn content_length(&self) -> Option<u64> {
+        self.numeric_header(CONTENT_LENGTH.as_str())
+    }
+
+    /// Gets how many requests the server still allows, from the `X-RateLimit-Remaining` header.
+    pub fn rate_limit_remaining(&self) -> Option<u64> {
+        self.numeric_header("x-ratelimit-remaining")
+    }
+
+    /// Gets the `X-RateLimit-Reset` header as sent. Depending on the API, it is either the number
+    /// of seconds until the limit resets or the unix time at which it does.
+    pub fn rate_limit_reset(&self) -> Option<u64> {
+        self.numeric_header("x-ratelimit-reset")
+    }
+
+    /// Gets the trailing headers of the response. reqwest drops the trailers of chunked HTTP/1.1
+    /// and HTTP/2 responses, so this is `None` for them; only binary gRPC-web responses, which
+    /// carry their trailers in a final frame of the body, have any.
+    pub fn trailers(&self) -> Option<HeaderMap> {
+        let content_type = self
+            .response_headers
+            .as_ref()?
+            .get(CONTENT_TYPE)?
+            .to_str()
+            .ok()?;
+        if !content_type.starts_with("application/grpc-web")
+            || content_type.starts_with("application/grpc-web-text")
+        {
+            return None;
+        }
+
+        grpc_web_trailers(self.response_body.as_ref()?)
+    }
+
+    fn numeric_header(&self, name: &str) -> Option<u64> {
+        self.response_headers
+            .as_ref()?
+            .get(name)?
+            .to_str()
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// Gets the number of response headers, or 0 if no response has been received.
+    pub fn header_count(&self) -> usize {
+        self.response_headers.as_ref().map_or(0, HeaderMap::len)
+    }
+
+    /// Returns a human-readable multiline summary of the current step, useful in `on_error`:
+    /// method, url, status, elapsed time, header counts, body size and a short body preview.
+    pub fn dump(&self) -> String {
+        const PREVIEW_BYTES: usize = 120;
+
+        let mut out = String::with_capacity(256);
+        let _ = writeln!(out, "{} {}", self.request.method_ref(), self.request.url());
+        let _ = writeln!(out, "step: {}", self.current_step.as_deref().unwrap_or("-"));
+        match self.status_code {
+            Some(status) => {
+                let _ = writeln!(out, "status: {}", status);
+            }
+            None => out.push_str("status: -\n"),
+        }
+        let _ = writeln!(out, "elapsed: {} ms", self.time_elapsed);
+        let _ = writeln!(
+            out,
+            "headers: {} sent, {} received",
+            self.request.headers().map_or(0, |headers| headers.len()),
+            self.header_count()
+        );
+        match &self.response_body {
+            Some(body) => {
+                let preview = String::from_utf8_lossy(&body[..body.len().min(PREVIEW_BYTES)]);
+                let _ = writeln!(out, "body: {} bytes", body.len());
+                let _ = write!(out, "preview: {}", preview.escape_debug());
+                if body.len() > PREVIEW_BYTES {
+                    out.push_str("...");
+                }
+            }
+            None => out.push_str("body: -"),
+        }
+
+        out
+    }
+
+    /// Stores a value under `key` for later steps, replacing any previous value.
+    pub fn set_state<T: Any + Send + Sync>(&mut self, key: &str, value: T) {
+        self.state.insert(key.to_string(), Box::new(value));
+    }
+
+    /// Gets the value stored under `key`, if there is one of type `T`.
+    pub fn get_state<T: Any + Send + Sync>(&self, key: &str) -> Option<&T> {
+        self.state.get(key)?.downcast_ref()
+    }
+
+    /// Stores every value of `other`, replacing existing values with the same key.
+    /// Useful when resuming a pipeline from a saved checkpoint.
+    pub fn extend_state(&mut self, other: HashMap<String, Box<dyn Any + Send + Sync>>) {
+        self.state.extend(other);
+    }
+
+    /// Gets the keys of the stored values, sorted.
+    pub fn state_keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.state.keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    /// Gets the filename the server suggests in the `Content-Disposition` header, preferring the
+    /// RFC 5987 `filename*=` form over `filename=`. Any directories are stripped from the name so
+    /// it is safe to join onto a download directory.
+    pub fn suggested_filename(&self) -> Option<String> {
+        let value = self
+            .response_headers
+            .as_ref()?
+            .get(CONTENT_DISPOSITION)?
+            .to_str()
+            .ok()?;
+
+        let mut plain = None;
+        let mut extended = None;
+        for (name, value) in disposition_params(value) {
+            if name.eq_ignore_ascii_case("filename*") {
+                extended = decode_ext_value(&value);
+            } else if name.eq_ignore_ascii_case("filename") {
+                plain = Some(value);
+            }
+        }
+
+        let name = extended.or(plain)?;
+        let name = name.rsplit(['/', '\\']).next()?.trim();
+        match name {
+            "" | "." | ".." => None,
+            name => Some(name.to_string()),
+        }
+    }
+
+    /// Writes the response body to `path`.
+    pub fn save_body_to_file(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, self.body_bytes()?)?;
+        Ok(())
+    }
+
+    /// Writes the response body into `dir`, named after `suggested_filename` or else the last
+    /// segment of the url, and returns the path written to.
+    pub fn save_body_to_dir(&self, dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        let name = self
+            .suggested_filename()
+            .or_else(|| {
+                let url = reqwest::Url::parse(self.request.url()).ok()?;
+                let segment = url.path_segments()?.next_back()?.to_string();
+                (!segment.is_empty()).then_some(segment)
+            })
+            .unwrap_or_else(|| "download".to_string());
+
+        let path = dir.join(name);
+        self.save_body_to_file(&path)?;
+        Ok(path)
+    }
+
+    /// Hashes the response body, to tell whether it changed since an earlier response.
+    /// Hashes are stable within a build but shouldn't be persisted across Rust versions.
+    pub fn body_hash(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        self.response_body.as_ref()?.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
     /// Sets the response body in bytes.
     pub fn set_response_body(&mut self, res: bytes::Bytes) {
         self.response_body = Some(res);
@@ -143,7 +569,17 @@ impl Context {
         Ok(text.to_string())
     }
 
+    /// Returns the response body encoded with standard, padded base64, e.g. to log a binary body.
+    pub fn body_base64(&self) -> Result<String, Box<dyn Error>> {
+        let body = self
+            .response_body
+            .as_ref()
+            .ok_or_else(Self::no_body_error)?;
+        Ok(STANDARD.encode(body))
+    }
+
     /// Returns the response body as JSON. This is a convenience method for `serde_json::from_slice`.
+    #[cfg(feature = "json")]
     pub async fn body_json<T: DeserializeOwned>(&self) -> Result<T, Box<dyn Error>> {
         if self.response_body.is_none() {
             return Err(Self::no_body_error());
@@ -153,9 +589,149 @@ impl Context {
             .map_err(|err| -> Box<dyn std::error::Error> { Box::new(err) })
     }
 
+    /// Returns the response body deserialized from MessagePack.
+    #[cfg(feature = "msgpack")]
+    pub fn body_msgpack<T: DeserializeOwned>(&self) -> Result<T, Box<dyn Error>> {
+        let body = self
+            .response_body
+            .as_ref()
+            .ok_or_else(Self::no_body_error)?;
+        Ok(rmp_serde::from_slice(body)?)
+    }
+
+    /// Returns the response body deserialized from CBOR.
+    #[cfg(feature = "cbor")]
+    pub fn body_cbor<T: DeserializeOwned>(&self) -> Result<T, Box<dyn Error>> {
+        let body = self
+            .response_body
+            .as_ref()
+            .ok_or_else(Self::no_body_error)?;
+        Ok(ciborium::de::from_reader(body.as_ref())?)
+    }
+
+    /// Returns the capture `group` of the first match of `pattern` in the body text, e.g. to grab
+    /// a token between two markers without parsing the page. Group `0` is the whole match.
+    /// `None` when nothing matches or the group didn't take part in the match. Errors when the
+    /// pattern is invalid or there is no body.
+    #[cfg(feature = "regex")]
+    pub fn regex_capture(
+        &self,
+        pattern: &str,
+        group: usize,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let regex = regex::Regex::new(pattern)?;
+        let text = self.body_text()?;
+        let capture = regex
+            .captures(&text)
+            .and_then(|captures| captures.get(group))
+            .map(|capture| capture.as_str().to_string());
+        Ok(capture)
+    }
+
+    /// Hands the raw response body to `parse`, for formats without a dedicated method.
+    /// Errors with the usual no-body error, without calling `parse`, when there is no body.
+    pub fn parse_body_with<T, E: Into<Box<dyn Error>>>(
+        &self,
+        parse: impl FnOnce(&[u8]) -> Result<T, E>,
+    ) -> Result<T, Box<dyn Error>> {
+        let body = self
+            .response_body
+            .as_ref()
+            .ok_or_else(Self::no_body_error)?;
+        parse(body).map_err(Into::into)
+    }
+
+    /// Sends the last request again with the same client settings and cookie store, and returns
+    /// the new response body. No step hooks run and the context is left untouched, which makes it
+    /// handy for checking whether a failure is transient. The status code is not checked.
+    /// The request waits for the worker's concurrency limits like any other.
+    pub async fn replay(&self) -> Result<bytes::Bytes, StepError> {
+        if self.current_step.is_none() {
+            return Err(StepError::Other(
+                "No request has been sent to replay.".to_string(),
+            ));
+        }
+
+        let url = self.http_requester.normalize_url(&self.get_url());
+        let _permits = self
+            .limits
+            .acquire(&url)
+            .await
+            .map_err(|err| StepError::Other(err.to_string()))?;
+        let builder = self
+            .http_requester
+            .build_reqwest_undecoded(self.request.clone())
+            .map_err(|err| StepError::ReqwestError(err.to_string()))?;
+        let res = self
+            .http_requester
+            .send(builder)
+            .await
+            .map_err(|err| StepError::ReqwestError(err.to_string()))?;
+
+        let headers = res.headers().clone();
+        let body = res
+            .bytes()
+            .await
+            .map_err(|err| StepError::ReqwestError(err.to_string()))?;
+        let limit = self.client_settings().max_decompressed_bytes();
+        self.http_requester.decode_body(&headers, body, limit)
+    }
+
+    /// Deserializes the elements of a JSON array body one at a time, so they can be processed
+    /// without building the whole `Vec<T>`. Iteration stops after the first error, which is also
+    /// yielded when there is no body or it isn't an array.
+    #[cfg(feature = "json")]
+    pub fn json_array_iter<'a, T: DeserializeOwned + 'a>(
+        &'a self,
+    ) -> impl Iterator<Item = Result<T, serde_json::Error>> + 'a {
+        JsonArrayIter {
+            bytes: self.response_body.as_deref(),
+            pos: 0,
+            state: JsonArrayState::Start,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Checks whether the response body is structurally equal to `expected`, ignoring key order.
+    /// Returns false if there is no body or it isn't valid JSON.
+    #[cfg(feature = "json")]
+    pub fn json_eq(&self, expected: &serde_json::Value) -> bool {
+        match &self.response_body {
+            Some(body) => serde_json::from_slice::<serde_json::Value>(body)
+                .map(|actual| &actual == expected)
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
+    /// Panics unless the response body is structurally equal to `expected`.
+    /// The panic message lists every differing path along with both documents pretty-printed.
+    #[cfg(all(feature = "json", any(test, feature = "test-util")))]
+    #[track_caller]
+    pub fn assert_json_eq(&self, expected: &serde_json::Value) {
+        let body = match &self.response_body {
+            Some(body) => body,
+            None => panic!("assert_json_eq: no body has been set from the request"),
+        };
+        let actual: serde_json::Value = match serde_json::from_slice(body) {
+            Ok(actual) => actual,
+            Err(err) => panic!("assert_json_eq: response body isn't valid JSON: {}", err),
+        };
+
+        let mut diffs = Vec::new();
+        json_diff("$", expected, &actual, &mut diffs);
+        if !diffs.is_empty() {
+            panic!(
+                "assert_json_eq: response body doesn't match\n{}\n\nexpected:\n{}\n\nactual:\n{}",
+                diffs.join("\n"),
+                serde_json::to_string_pretty(expected).unwrap_or_default(),
+                serde_json::to_string_pretty(&actual).unwrap_or_default()
+            );
+        }
+    }
+
     fn no_body_error() -> Box<dyn Error> {
-        Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        Box::new(std::io::Error::other(
             "No body has been set from the request.",
         ))
     }
@@ -163,23 +739,27 @@ impl Context {
     /// Updates the context from the request.
     /// This is useful for updating the success status codes, proxy, user agent, and compression settings.
     pub fn update_from_request(&mut self, req: Request) -> Result<(), Box<dyn Error>> {
-        self.http_requester.settings.set_proxy(req.proxy());
-        self.http_requester
-            .settings
-            .set_user_agent(req.user_agent());
+        match req.proxy() {
+            Some(proxy) => {
+                self.http_requester.settings.set_proxy(Some(proxy));
+            }
+            None => self.http_requester.settings.use_next_rotating_proxy(),
+        }
+        let user_agent = req
+            .user_agent()
+            .or_else(|| self.http_requester.settings.next_user_agent());
+        self.http_requester.settings.set_user_agent(user_agent);
         self.http_requester
             .settings
             .set_compression(req.is_compressed());
 
         self.status_codes = req.status_codes().clone();
 
-        if let Ok(builder) = self.http_requester.build_reqwest(req.clone()) {
+        if let Ok(builder) = self.http_requester.build_reqwest_undecoded(req.clone()) {
+            self.sent_request = self.http_requester.sent_request(&builder, &req);
             self.request_builder = Some(builder);
         } else {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Unable to build request",
-            )));
+            return Err(Box::new(std::io::Error::other("Unable to build request")));
         }
 
         self.request = req;
@@ -188,9 +768,213 @@ impl Context {
     }
 }
 
+// splits the parameters of a header like `attachment; filename="a;b.txt"` into name-value pairs,
+// unquoting quoted values
+fn disposition_params(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = match value.split_once(';') {
+        Some((_, params)) => params,
+        None => return params,
+    };
+
+    while let Some((name, after)) = rest.split_once('=') {
+        let name = name.trim().to_string();
+        let after = after.trim_start();
+
+        if let Some(quoted) = after.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some((_, escaped)) = chars.next() {
+                            value.push(escaped);
+                        }
+                    }
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+            params.push((name, value));
+            rest = quoted[end..].split_once(';').map_or("", |(_, rest)| rest);
+        } else {
+            let (value, next) = after.split_once(';').unwrap_or((after, ""));
+            params.push((name, value.trim().to_string()));
+            rest = next;
+        }
+    }
+
+    params
+}
+
+// decodes an RFC 5987 value like `UTF-8''%E2%82%AC%20rates.txt`
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?;
+    let _language = parts.next()?;
+    let encoded = parts.next()?;
+
+    let bytes: Vec<u8> = percent_decode_str(encoded).collect();
+    if charset.eq_ignore_ascii_case("utf-8") {
+        String::from_utf8(bytes).ok()
+    } else if charset.eq_ignore_ascii_case("iso-8859-1") {
+        Some(bytes.into_iter().map(char::from).collect())
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "json")]
+enum JsonArrayState {
+    Start,
+    Next,
+    Done,
+}
+
+#[cfg(feature = "json")]
+struct JsonArrayIter<'a, T> {
+    bytes: Option<&'a [u8]>,
+    pos: usize,
+    state: JsonArrayState,
+    _marker: std::marker::PhantomData<T>,
+}
+
+#[cfg(feature = "json")]
+impl<T: DeserializeOwned> JsonArrayIter<'_, T> {
+    // moves past whitespace and returns the next byte, if any
+    fn peek(&mut self, bytes: &[u8]) -> Option<u8> {
+        while bytes.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+        bytes.get(self.pos).copied()
+    }
+
+    fn fail(&mut self, msg: &str) -> Option<Result<T, serde_json::Error>> {
+        self.state = JsonArrayState::Done;
+        Some(Err(serde::de::Error::custom(msg)))
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: DeserializeOwned> Iterator for JsonArrayIter<'_, T> {
+    type Item = Result<T, serde_json::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = match self.bytes {
+            Some(bytes) => bytes,
+            None if matches!(self.state, JsonArrayState::Done) => return None,
+            None => return self.fail("No body has been set from the request."),
+        };
+
+        match self.state {
+            JsonArrayState::Done => return None,
+            JsonArrayState::Start => {
+                if self.peek(bytes) != Some(b'[') {
+                    return self.fail("expected a JSON array");
+                }
+                self.pos += 1;
+                if self.peek(bytes) == Some(b']') {
+                    self.state = JsonArrayState::Done;
+                    return None;
+                }
+            }
+            JsonArrayState::Next => match self.peek(bytes) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.state = JsonArrayState::Done;
+                    return None;
+                }
+                _ => return self.fail("expected `,` or `]` after an array element"),
+            },
+        }
+
+        let mut stream = serde_json::Deserializer::from_slice(&bytes[self.pos..]).into_iter::<T>();
+        match stream.next() {
+            Some(Ok(item)) => {
+                self.pos += stream.byte_offset();
+                self.state = JsonArrayState::Next;
+                Some(Ok(item))
+            }
+            Some(Err(err)) => {
+                self.state = JsonArrayState::Done;
+                Some(Err(err))
+            }
+            None => self.fail("unexpected end of the JSON array"),
+        }
+    }
+}
+
+// collects a line per path where the two values differ
+#[cfg(all(feature = "json", any(test, feature = "test-util")))]
+fn json_diff(
+    path: &str,
+    expected: &serde_json::Value,
+    actual: &serde_json::Value,
+    diffs: &mut Vec<String>,
+) {
+    use serde_json::Value;
+
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, value) in expected {
+                let path = format!("{}.{}", path, key);
+                match actual.get(key) {
+                    Some(other) => json_diff(&path, value, other, diffs),
+                    None => diffs.push(format!("  {}: missing, expected {}", path, value)),
+                }
+            }
+            for (key, value) in actual {
+                if !expected.contains_key(key) {
+                    diffs.push(format!("  {}.{}: unexpected {}", path, key, value));
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) if expected.len() == actual.len() => {
+            for (i, (value, other)) in expected.iter().zip(actual).enumerate() {
+                json_diff(&format!("{}[{}]", path, i), value, other, diffs);
+            }
+        }
+        _ if expected != actual => {
+            diffs.push(format!("  {}: expected {}, got {}", path, expected, actual));
+        }
+        _ => {}
+    }
+}
+
+// gRPC-web frames are a flag byte, a big-endian u32 length and the payload; the trailer frame
+// is flagged 0x80 and holds HTTP/1 style header lines
+fn grpc_web_trailers(mut body: &[u8]) -> Option<HeaderMap> {
+    while body.len() >= 5 {
+        let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+        let payload = body.get(5..5 + len)?;
+
+        if body[0] & 0x80 != 0 {
+            let mut trailers = HeaderMap::new();
+            for line in std::str::from_utf8(payload).ok()?.split("\r\n") {
+                if let Some((name, value)) = line.split_once(':') {
+                    trailers.append(
+                        HeaderName::from_bytes(name.trim().as_bytes()).ok()?,
+                        HeaderValue::from_str(value.trim()).ok()?,
+                    );
+                }
+            }
+            return Some(trailers);
+        }
+
+        body = &body[5 + len..];
+    }
+
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{UserAgentRotation, BROWSER_USER_AGENTS};
 
     #[test]
     fn context_should_get_current_step_without_one_set() {
@@ -198,6 +982,19 @@ mod tests {
         assert_eq!(ctx.get_current_step(), None);
     }
 
+    #[test]
+    fn context_should_get_time_elapsed_as_a_duration() {
+        let mut ctx = Context::new();
+        assert_eq!(ctx.time_elapsed_duration(), Duration::ZERO);
+
+        ctx.set_time_elapsed(1_250);
+        assert_eq!(ctx.time_elapsed_duration(), Duration::from_millis(1_250));
+        assert_eq!(
+            ctx.time_elapsed_duration().as_millis() as u64,
+            ctx.get_time_elapsed()
+        );
+    }
+
     #[test]
     fn context_should_get_url_without_one_set() {
         let ctx = Context::new();
@@ -220,6 +1017,56 @@ mod tests {
         assert_eq!(err.to_string(), "No body has been set from the request.");
     }
 
+    #[test]
+    fn context_body_base64_should_encode_the_body() {
+        let mut ctx = Context::new();
+        let err = ctx.body_base64().unwrap_err();
+        assert_eq!(err.to_string(), "No body has been set from the request.");
+
+        ctx.set_response_body(bytes::Bytes::from_static(&[0x00, 0xff, 0x10, 0x80, 0x7f]));
+        assert_eq!(ctx.body_base64().unwrap(), "AP8QgH8=");
+    }
+
+    #[test]
+    fn context_should_parse_the_body_with_a_closure() {
+        // every record is a length byte followed by that many bytes of text
+        fn records(body: &[u8]) -> Result<Vec<String>, String> {
+            let mut records = Vec::new();
+            let mut rest = body;
+            while let Some((&len, tail)) = rest.split_first() {
+                if tail.len() < len as usize {
+                    return Err(format!("record of {} bytes is truncated", len));
+                }
+                let (record, tail) = tail.split_at(len as usize);
+                records.push(String::from_utf8_lossy(record).to_string());
+                rest = tail;
+            }
+            Ok(records)
+        }
+
+        let mut ctx = Context::new();
+        let mut called = false;
+        let err = ctx
+            .parse_body_with(|body| {
+                called = true;
+                records(body)
+            })
+            .unwrap_err();
+        assert_eq!(err.to_string(), "No body has been set from the request.");
+        assert!(!called);
+
+        ctx.set_response_body(bytes::Bytes::from_static(b"\x03foo\x00\x06barbaz"));
+        assert_eq!(
+            ctx.parse_body_with(records).unwrap(),
+            vec!["foo", "", "barbaz"]
+        );
+
+        ctx.set_response_body(bytes::Bytes::from_static(b"\x05abc"));
+        let err = ctx.parse_body_with(records).unwrap_err();
+        assert_eq!(err.to_string(), "record of 5 bytes is truncated");
+    }
+
+    #[cfg(feature = "json")]
     #[tokio::test]
     async fn context_body_json_should_mock_response_and_get_name() {
         let mut ctx = Context::new();
@@ -230,6 +1077,7 @@ mod tests {
         assert_eq!(json["name"], "test");
     }
 
+    #[cfg(feature = "json")]
     #[tokio::test]
     async fn context_body_json_should_return_error_if_invalid_json() {
         let mut ctx = Context::new();
@@ -239,4 +1087,362 @@ mod tests {
         let err = ctx.body_json::<serde_json::Value>().await.unwrap_err();
         assert!(!err.to_string().is_empty());
     }
+
+    #[test]
+    fn context_should_read_the_content_length_header() {
+        let mut ctx = Context::new();
+        assert_eq!(ctx.content_length(), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, "1024".parse().unwrap());
+        ctx.set_response_headers(headers);
+
+        assert_eq!(ctx.content_length(), Some(1024));
+    }
+
+    #[test]
+    fn context_should_read_the_rate_limit_headers() {
+        let mut ctx = Context::new();
+        assert_eq!(ctx.rate_limit_remaining(), None);
+        assert_eq!(ctx.rate_limit_reset(), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-RateLimit-Remaining", "0".parse().unwrap());
+        headers.insert("X-RateLimit-Reset", "1700000000".parse().unwrap());
+        ctx.set_response_headers(headers);
+
+        assert_eq!(ctx.rate_limit_remaining(), Some(0));
+        assert_eq!(ctx.rate_limit_reset(), Some(1_700_000_000));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-RateLimit-Remaining", "unlimited".parse().unwrap());
+        ctx.set_response_headers(headers);
+
+        assert_eq!(ctx.rate_limit_remaining(), None);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn context_json_eq_should_ignore_key_order() {
+        let mut ctx = Context::new();
+        assert!(!ctx.json_eq(&serde_json::json!({})));
+
+        ctx.set_response_body(bytes::Bytes::from_static(
+            b"{\"user\": {\"id\": 7, \"tags\": [\"a\", \"b\"]}, \"ok\": true}",
+        ));
+
+        let expected = serde_json::json!({"ok": true, "user": {"tags": ["a", "b"], "id": 7}});
+        assert!(ctx.json_eq(&expected));
+        ctx.assert_json_eq(&expected);
+
+        assert!(
+            !ctx.json_eq(&serde_json::json!({"ok": true, "user": {"id": 8, "tags": ["a", "b"]}}))
+        );
+        assert!(
+            !ctx.json_eq(&serde_json::json!({"ok": true, "user": {"id": 7, "tags": ["b", "a"]}}))
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn context_assert_json_eq_should_report_differing_paths() {
+        let mut ctx = Context::new();
+        ctx.set_response_body(bytes::Bytes::from_static(
+            b"{\"user\": {\"id\": 7, \"name\": \"bob\"}, \"extra\": 1}",
+        ));
+
+        let expected =
+            serde_json::json!({"user": {"id": 8, "name": "bob", "email": "b@example.com"}});
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            ctx.assert_json_eq(&expected)
+        }))
+        .unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+
+        assert!(message.contains("$.user.id: expected 8, got 7"));
+        assert!(message.contains("$.user.email: missing"));
+        assert!(message.contains("$.extra: unexpected 1"));
+        assert!(!message.contains("$.user.name"));
+    }
+
+    #[test]
+    fn context_dump_should_summarize_the_response() {
+        let mut ctx = Context::new();
+        ctx.update_from_request(Request::new(
+            reqwest::Method::GET,
+            "https://example.com/items".to_string(),
+        ))
+        .unwrap();
+        ctx.set_current_step("Items".to_string());
+        ctx.set_status_code(503);
+        ctx.set_time_elapsed(42);
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, "300".parse().unwrap());
+        ctx.set_response_headers(headers);
+        ctx.set_response_body(bytes::Bytes::from("line\n".repeat(50)));
+
+        let dump = ctx.dump();
+
+        assert_eq!(ctx.header_count(), 1);
+        assert!(dump.starts_with("GET https://example.com/items\n"));
+        assert!(dump.contains("step: Items\n"));
+        assert!(dump.contains("status: 503\n"));
+        assert!(dump.contains("elapsed: 42 ms\n"));
+        assert!(dump.contains("headers: 0 sent, 1 received\n"));
+        assert!(dump.contains("body: 250 bytes\npreview: line\\nline"));
+        assert!(dump.ends_with("..."));
+    }
+
+    #[cfg(any(feature = "msgpack", feature = "cbor"))]
+    #[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, PartialEq)]
+    struct Profile {
+        id: u32,
+        tags: Vec<String>,
+    }
+
+    // sets the body the request would send as the response body, to read it back
+    #[cfg(any(feature = "msgpack", feature = "cbor"))]
+    fn echo(req: Request) -> Context {
+        let body = req.body().unwrap();
+        let mut ctx = Context::new();
+        ctx.set_response_body(bytes::Bytes::copy_from_slice(body.as_bytes().unwrap()));
+        ctx
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn context_should_round_trip_msgpack_bodies() {
+        let profile = Profile {
+            id: 7,
+            tags: vec!["a".to_string(), "b".to_string()],
+        };
+        let req = Request::new(reqwest::Method::POST, "https://example.com".to_string())
+            .with_msgpack(&profile)
+            .unwrap();
+
+        assert_eq!(
+            req.headers().unwrap()["Content-Type"],
+            "application/msgpack"
+        );
+        assert_eq!(echo(req).body_msgpack::<Profile>().unwrap(), profile);
+        assert!(Context::new().body_msgpack::<Profile>().is_err());
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn context_should_round_trip_cbor_bodies() {
+        let profile = Profile {
+            id: 7,
+            tags: vec!["a".to_string(), "b".to_string()],
+        };
+        let req = Request::new(reqwest::Method::POST, "https://example.com".to_string())
+            .with_cbor(&profile)
+            .unwrap();
+
+        assert_eq!(req.headers().unwrap()["Content-Type"], "application/cbor");
+        assert_eq!(echo(req).body_cbor::<Profile>().unwrap(), profile);
+        assert!(Context::new().body_cbor::<Profile>().is_err());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn context_should_capture_a_regex_group_from_the_body() {
+        let mut ctx = Context::new();
+        assert!(ctx.regex_capture("token", 0).is_err());
+
+        ctx.set_response_body(bytes::Bytes::from_static(
+            b"<input name=\"csrf\" value=\"a1b2c3\"><input name=\"next\" value=\"/home\">",
+        ));
+
+        let token = ctx.regex_capture(r#"name="csrf" value="([^"]+)""#, 1);
+        assert_eq!(token.unwrap().as_deref(), Some("a1b2c3"));
+        let whole = ctx.regex_capture(r#"value="/\w+""#, 0).unwrap();
+        assert_eq!(whole.as_deref(), Some(r#"value="/home""#));
+        assert_eq!(ctx.regex_capture("missing", 0).unwrap(), None);
+        assert_eq!(ctx.regex_capture("(csrf)", 2).unwrap(), None);
+        assert!(ctx.regex_capture("(unclosed", 1).is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn context_json_array_iter_should_yield_each_element() {
+        #[derive(serde_derive::Deserialize, Debug, PartialEq)]
+        struct Item {
+            id: u32,
+            name: String,
+        }
+
+        let mut ctx = Context::new();
+        assert!(ctx.json_array_iter::<Item>().next().unwrap().is_err());
+
+        ctx.set_response_body(bytes::Bytes::from_static(
+            b" [ {\"id\": 1, \"name\": \"a\"},\n{\"id\": 2, \"name\": \"b\"} , {\"id\": 3, \"name\": \"c\"}]",
+        ));
+        let items: Vec<Item> = ctx.json_array_iter().map(Result::unwrap).collect();
+        assert_eq!(items.len(), 3);
+        assert_eq!(
+            items[1],
+            Item {
+                id: 2,
+                name: "b".to_string()
+            }
+        );
+
+        ctx.set_response_body(bytes::Bytes::from_static(b"[]"));
+        assert_eq!(ctx.json_array_iter::<u32>().count(), 0);
+
+        ctx.set_response_body(bytes::Bytes::from_static(b"[1, 2 3]"));
+        let results: Vec<_> = ctx.json_array_iter::<u32>().collect();
+        assert_eq!(results.len(), 3);
+        assert_eq!(*results[1].as_ref().unwrap(), 2);
+        assert!(results[2].is_err());
+
+        ctx.set_response_body(bytes::Bytes::from_static(b"{\"id\": 1}"));
+        assert!(ctx.json_array_iter::<u32>().next().unwrap().is_err());
+    }
+
+    #[test]
+    fn context_should_merge_state_maps() {
+        let mut ctx = Context::new();
+        ctx.set_state("page", 1u32);
+        ctx.set_state("token", "old".to_string());
+
+        let mut checkpoint: HashMap<String, Box<dyn Any + Send + Sync>> = HashMap::new();
+        checkpoint.insert("token".to_string(), Box::new("resumed".to_string()));
+        checkpoint.insert("seen".to_string(), Box::new(vec![1u64, 2, 3]));
+        ctx.extend_state(checkpoint);
+
+        assert_eq!(ctx.state_keys(), vec!["page", "seen", "token"]);
+        assert_eq!(ctx.get_state::<u32>("page"), Some(&1));
+        assert_eq!(ctx.get_state::<String>("token").unwrap(), "resumed");
+        assert_eq!(ctx.get_state::<Vec<u64>>("seen").unwrap().len(), 3);
+        assert_eq!(ctx.get_state::<u64>("page"), None, "wrong type");
+        assert_eq!(ctx.get_state::<u32>("missing"), None);
+    }
+
+    fn context_with_disposition(value: &str) -> Context {
+        let mut ctx = Context::new();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_DISPOSITION, value.parse().unwrap());
+        ctx.set_response_headers(headers);
+        ctx
+    }
+
+    #[test]
+    fn context_should_extract_a_plain_suggested_filename() {
+        let ctx = context_with_disposition("attachment; filename=\"report; 2024.pdf\"");
+        assert_eq!(
+            ctx.suggested_filename().as_deref(),
+            Some("report; 2024.pdf")
+        );
+
+        let ctx = context_with_disposition("attachment; filename=data.csv");
+        assert_eq!(ctx.suggested_filename().as_deref(), Some("data.csv"));
+
+        let ctx = context_with_disposition("attachment; filename=\"../../etc/passwd\"");
+        assert_eq!(ctx.suggested_filename().as_deref(), Some("passwd"));
+
+        assert_eq!(
+            context_with_disposition("inline").suggested_filename(),
+            None
+        );
+        assert_eq!(Context::new().suggested_filename(), None);
+    }
+
+    #[test]
+    fn context_should_prefer_the_rfc5987_filename() {
+        let ctx = context_with_disposition(
+            "attachment; filename=\"rates.txt\"; filename*=UTF-8''%E2%82%AC%20rates.txt",
+        );
+        assert_eq!(ctx.suggested_filename().as_deref(), Some("€ rates.txt"));
+
+        let ctx = context_with_disposition("attachment; filename*=iso-8859-1'en'%A3%20rates.txt");
+        assert_eq!(ctx.suggested_filename().as_deref(), Some("£ rates.txt"));
+    }
+
+    #[test]
+    fn context_should_save_the_body_under_the_suggested_filename() {
+        let dir = std::env::temp_dir().join(format!("mimicr-download-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut ctx = context_with_disposition("attachment; filename=\"notes.txt\"");
+        ctx.set_response_body(bytes::Bytes::from_static(b"hello"));
+        let path = ctx.save_body_to_dir(&dir).unwrap();
+
+        assert_eq!(path, dir.join("notes.txt"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn context_body_hash_should_change_with_the_body() {
+        let mut ctx = Context::new();
+        assert_eq!(ctx.body_hash(), None);
+
+        ctx.set_response_body(bytes::Bytes::from_static(b"<html>v1</html>"));
+        let first = ctx.body_hash().unwrap();
+        ctx.set_response_body(bytes::Bytes::from_static(b"<html>v1</html>"));
+        assert_eq!(ctx.body_hash(), Some(first));
+
+        ctx.set_response_body(bytes::Bytes::from_static(b"<html>v2</html>"));
+        assert_ne!(ctx.body_hash(), Some(first));
+    }
+
+    #[test]
+    fn sent_request_should_include_the_client_default_headers() {
+        let mut ctx = Context::new();
+        let url = reqwest::Url::parse("https://example.com/").unwrap();
+        ctx.http_requester()
+            .cookies()
+            .parse("session=abc", &url)
+            .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Token", "t0k3n".parse().unwrap());
+        headers.insert("Accept", "application/json".parse().unwrap());
+        let req = Request::new(reqwest::Method::POST, url.to_string())
+            .with_headers(headers)
+            .with_user_agent("mimicr".to_string());
+        ctx.update_from_request(req).unwrap();
+
+        let sent = ctx.sent_request().unwrap();
+        assert_eq!(sent.method, reqwest::Method::POST);
+        assert_eq!(sent.url, "https://example.com/");
+        assert_eq!(sent.headers["X-Token"], "t0k3n");
+        assert_eq!(sent.headers["Accept"], "application/json");
+        assert_eq!(sent.headers["User-Agent"], "mimicr");
+        assert_eq!(sent.headers["Accept-Encoding"], "gzip");
+        assert_eq!(sent.headers["Cookie"], "session=abc");
+    }
+
+    #[test]
+    fn context_should_send_random_browser_user_agents() {
+        let mut ctx = Context::new();
+        ctx.client_settings_mut()
+            .use_random_browser_ua(UserAgentRotation::Once);
+        let picked = ctx.client_settings().user_agent().cloned().unwrap();
+        assert!(BROWSER_USER_AGENTS.contains(&picked.as_str()));
+
+        let req = Request::new(reqwest::Method::GET, "https://example.com".to_string());
+        for _ in 0..3 {
+            ctx.update_from_request(req.clone()).unwrap();
+            assert_eq!(ctx.client_settings().user_agent(), Some(&picked));
+        }
+        ctx.update_from_request(req.clone().with_user_agent("mimicr".to_string()))
+            .unwrap();
+        ctx.update_from_request(req.clone()).unwrap();
+        assert_eq!(ctx.client_settings().user_agent(), Some(&picked));
+
+        ctx.client_settings_mut()
+            .use_random_browser_ua(UserAgentRotation::PerRequest);
+        for _ in 0..3 {
+            ctx.update_from_request(req.clone()).unwrap();
+            let user_agent = ctx.client_settings().user_agent().unwrap();
+            assert!(BROWSER_USER_AGENTS.contains(&user_agent.as_str()));
+        }
+
+        ctx.update_from_request(req.clone().with_user_agent("mimicr".to_string()))
+            .unwrap();
+        assert_eq!(ctx.client_settings().user_agent().unwrap(), "mimicr");
+    }
 }
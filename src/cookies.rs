@@ -0,0 +1,126 @@
+//! The cookie jar shared by a requester and the clients it builds, see `HttpRequester::from_client`.
+
+use std::sync::{Arc, LockResult, Mutex, MutexGuard, TryLockError, TryLockResult};
+
+use bytes::Bytes;
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+use reqwest_cookie_store::{CookieStore, CookieStoreMutex, RawCookie};
+
+/// A cookie store behind a `std::sync::Mutex`, handed to reqwest clients as their cookie
+/// provider. Unlike `reqwest_cookie_store::CookieStoreMutex` it can also be locked without
+/// blocking, see `HttpRequester::try_get_cookies`.
+///
+/// A client built around a `CookieStoreMutex` can still be passed to `HttpRequester::from_client`
+/// by converting its store with `CookieJar::from`: the jar then shares that mutex with the client,
+/// and `try_lock` waits for it like `lock`.
+#[derive(Debug, Default)]
+pub struct CookieJar(Store);
+
+#[derive(Debug)]
+enum Store {
+    Owned(Mutex<CookieStore>),
+    Shared(Arc<CookieStoreMutex>),
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Store::Owned(Mutex::default())
+    }
+}
+
+impl CookieJar {
+    pub fn new(cookie_store: CookieStore) -> Self {
+        CookieJar(Store::Owned(Mutex::new(cookie_store)))
+    }
+
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, CookieStore>> {
+        match &self.0 {
+            Store::Owned(store) => store.lock(),
+            Store::Shared(store) => store.lock(),
+        }
+    }
+
+    /// Locks the store unless another thread holds it.
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, CookieStore>> {
+        match &self.0 {
+            Store::Owned(store) => store.try_lock(),
+            // `CookieStoreMutex` has no non-blocking lock
+            Store::Shared(store) => store.lock().map_err(TryLockError::from),
+        }
+    }
+
+    // the client keeps using a store poisoned by a panic, as the requester does
+    fn store(&self) -> MutexGuard<'_, CookieStore> {
+        self.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl From<Arc<CookieStoreMutex>> for CookieJar {
+    fn from(cookie_store: Arc<CookieStoreMutex>) -> Self {
+        CookieJar(Store::Shared(cookie_store))
+    }
+}
+
+impl reqwest::cookie::CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let cookies = cookie_headers.filter_map(|value| {
+            let value = std::str::from_utf8(value.as_bytes()).ok()?;
+            RawCookie::parse(value)
+                .map(|cookie| cookie.into_owned())
+                .ok()
+        });
+        self.store().store_response_cookies(cookies, url);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let cookies = self
+            .store()
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if cookies.is_empty() {
+            return None;
+        }
+
+        HeaderValue::from_maybe_shared(Bytes::from(cookies)).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::cookie::CookieStore as _;
+
+    use super::*;
+
+    #[test]
+    fn it_should_store_and_send_cookies_as_a_client_provider() {
+        let jar = CookieJar::default();
+        let url = Url::parse("https://example.com/account").unwrap();
+        let set_cookie = HeaderValue::from_static("session=abc; Path=/");
+
+        jar.set_cookies(&mut std::iter::once(&set_cookie), &url);
+
+        assert_eq!(jar.cookies(&url).unwrap(), "session=abc");
+        let other = Url::parse("https://example.org/").unwrap();
+        assert!(jar.cookies(&other).is_none());
+    }
+
+    #[test]
+    fn a_jar_converted_from_a_cookie_store_mutex_should_share_its_cookies() {
+        let store = Arc::new(CookieStoreMutex::default());
+        let jar = CookieJar::from(store.clone());
+        let url = Url::parse("https://example.com/").unwrap();
+        let set_cookie = HeaderValue::from_static("session=abc");
+
+        store.set_cookies(&mut std::iter::once(&set_cookie), &url);
+
+        assert_eq!(jar.cookies(&url).unwrap(), "session=abc");
+        assert!(jar
+            .try_lock()
+            .unwrap()
+            .get("example.com", "/", "session")
+            .is_some());
+    }
+}
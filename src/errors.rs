@@ -1,11 +1,80 @@
 use std::error::Error;
 use std::fmt;
 
+use crate::JsonKind;
+
+/// The phase of a request that timed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// Connecting to the server, which points at an unreachable host.
+    Connect,
+    /// Reading the body after the response headers arrived, which points at a slow transfer.
+    Read,
+    /// Waiting for the response, which points at a slow server.
+    Overall,
+}
+
+impl TimeoutPhase {
+    pub(crate) fn from_reqwest(err: &reqwest::Error, reading_body: bool) -> Self {
+        if err.is_connect() {
+            TimeoutPhase::Connect
+        } else if reading_body {
+            TimeoutPhase::Read
+        } else {
+            TimeoutPhase::Overall
+        }
+    }
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeoutPhase::Connect => write!(f, "connect"),
+            TimeoutPhase::Read => write!(f, "read"),
+            TimeoutPhase::Overall => write!(f, "overall"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum StepError {
     ReqwestError(String),
     StepNotFound(String),
     StatusCodeNotFound(i32, Vec<u16>),
+    UnexpectedContentType {
+        expected: String,
+        got: String,
+    },
+    /// The body is JSON of another kind than expected, or `got` is `None` when it isn't JSON.
+    UnexpectedJsonKind {
+        expected: JsonKind,
+        got: Option<JsonKind>,
+    },
+    DecompressionLimitExceeded(usize),
+    /// The body is shorter than the request's minimum size.
+    BodyTooSmall {
+        got: usize,
+        min: usize,
+    },
+    ContentLengthMismatch {
+        expected: u64,
+        got: u64,
+    },
+    Timeout {
+        phase: TimeoutPhase,
+    },
+    Other(String),
+}
+
+impl StepError {
+    /// Converts any error returned while running a step into a `StepError`,
+    /// keeping it as-is when it already is one.
+    pub(crate) fn from_error(err: &(dyn Error + 'static)) -> Self {
+        match err.downcast_ref::<StepError>() {
+            Some(err) => err.clone(),
+            None => StepError::Other(err.to_string()),
+        }
+    }
 }
 
 impl fmt::Display for StepError {
@@ -20,8 +89,107 @@ impl fmt::Display for StepError {
                     code, expected_codes
                 )
             }
+            StepError::UnexpectedContentType { expected, got } => {
+                write!(
+                    f,
+                    "Unexpected content type {:?}. Expected: {:?}",
+                    got, expected
+                )
+            }
+            StepError::UnexpectedJsonKind { expected, got } => match got {
+                Some(got) => write!(f, "Unexpected JSON {}. Expected: {}", got, expected),
+                None => write!(f, "Body isn't valid JSON. Expected: {}", expected),
+            },
+            StepError::DecompressionLimitExceeded(limit) => {
+                write!(f, "Decompressed body exceeded the limit of {} bytes", limit)
+            }
+            StepError::BodyTooSmall { got, min } => {
+                write!(f, "Body of {} bytes is smaller than {} bytes", got, min)
+            }
+            StepError::ContentLengthMismatch { expected, got } => {
+                write!(
+                    f,
+                    "Body size {} doesn't match Content-Length {}",
+                    got, expected
+                )
+            }
+            StepError::Timeout { phase } => write!(f, "Request timed out ({})", phase),
+            StepError::Other(err) => write!(f, "{}", err),
         }
     }
 }
 
 impl Error for StepError {}
+
+#[derive(Debug, Clone)]
+pub enum CookieError {
+    Serialization(String),
+    /// A cookie couldn't be added to the store, with the reason.
+    Invalid(String),
+}
+
+impl fmt::Display for CookieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CookieError::Serialization(err) => {
+                write!(f, "Unable to serialize cookies: {}", err)
+            }
+            CookieError::Invalid(err) => {
+                write!(f, "Invalid cookie: {}", err)
+            }
+        }
+    }
+}
+
+impl Error for CookieError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestError {
+    EmptyUrl,
+    /// A request can't carry both a body and a multipart form.
+    BodyAndMultipart,
+    /// `Request::with_proxy` was called twice, dropping the first proxy.
+    ProxySetTwice,
+    InvalidHeaderValue(String),
+    /// The body couldn't be serialized, e.g. by `Request::with_msgpack`.
+    Serialization(String),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestError::EmptyUrl => write!(f, "Request url is empty"),
+            RequestError::BodyAndMultipart => {
+                write!(f, "Request has both a body and a multipart form set")
+            }
+            RequestError::ProxySetTwice => write!(f, "Request proxy was set twice"),
+            RequestError::InvalidHeaderValue(name) => {
+                write!(f, "Invalid value for header: {}", name)
+            }
+            RequestError::Serialization(err) => {
+                write!(f, "Unable to serialize request body: {}", err)
+            }
+        }
+    }
+}
+
+impl Error for RequestError {}
+
+/// Why a `reqwest::Request` couldn't be converted with `Request::from_reqwest`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConvertError {
+    /// The body is a stream, which can't be read without consuming it asynchronously.
+    StreamingBody,
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConvertError::StreamingBody => {
+                write!(f, "Streaming request bodies can't be converted")
+            }
+        }
+    }
+}
+
+impl Error for ConvertError {}
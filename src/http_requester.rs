@@ -1,20 +1,62 @@
-use std::sync::Arc;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use reqwest::header::HeaderMap;
-use reqwest::{Body, Client, IntoUrl, Method, RequestBuilder, Response};
-use reqwest_cookie_store::{CookieStore, CookieStoreMutex};
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH,
+    CONTENT_TYPE, COOKIE, USER_AGENT,
+};
+use reqwest::multipart::Form;
+use reqwest::{Body, Client, ClientBuilder, IntoUrl, Method, RequestBuilder, Response, Url};
+use reqwest_cookie_store::CookieStore;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
 
 // http_requester.rs
 use crate::client_settings::ClientSettings;
-use crate::request::Request;
+use crate::cookies::CookieJar;
+use crate::errors::{CookieError, StepError, TimeoutPhase};
+#[cfg(any(test, feature = "test-util"))]
+use crate::mock::MockTransport;
+use crate::normalize::UrlNormalizer;
+use crate::request::{RawRequest, Request, SentRequest};
 
 #[derive(Clone)]
 pub struct HttpRequester {
-    cookie_store: Arc<CookieStoreMutex>,
+    cookie_store: Arc<CookieJar>,
     pub settings: Box<ClientSettings>,
+    /// A client supplied by the caller, used instead of building one from `settings`.
+    client: Option<Client>,
+    /// Clients built so far, reused with their connection pools by requests needing the same.
+    clients: Arc<Mutex<HashMap<ClientKey, Client>>>,
+    /// Edits the headers of every request built.
+    header_transform: Option<HeaderTransform>,
+    /// Rewrites the url of every request built.
+    url_normalizer: Option<Arc<dyn UrlNormalizer>>,
+    /// Answers requests with canned responses instead of sending them.
+    #[cfg(any(test, feature = "test-util"))]
+    mock: Option<Arc<MockTransport>>,
 }
 
+// everything a built client depends on, since a client can't be changed once built
+#[derive(PartialEq, Eq, Hash)]
+struct ClientKey {
+    proxy: Option<String>,
+    user_agent: Option<String>,
+    gzip: bool,
+    accept_invalid_certs: bool,
+    cookie_store: Option<usize>,
+    host_overrides: Vec<(String, SocketAddr)>,
+}
+
+/// Edits the headers of every request before it is sent. See `Worker::set_header_transform`.
+pub type HeaderTransform = Arc<dyn Fn(&mut HeaderMap) + Send + Sync>;
+
 impl Default for HttpRequester {
     fn default() -> Self {
         HttpRequester::new()
@@ -29,15 +71,133 @@ impl HttpRequester {
         Self {
             cookie_store,
             settings: Box::new(settings),
+            client: None,
+            clients: Arc::default(),
+            header_transform: None,
+            url_normalizer: None,
+            #[cfg(any(test, feature = "test-util"))]
+            mock: None,
+        }
+    }
+
+    /// Creates a requester around a pre-built client, for needs `ClientSettings` can't express
+    /// (custom TLS, connectors, etc.).
+    ///
+    /// The client is used as-is for every request: proxy, user agent, compression and any other
+    /// `settings` are ignored and become the caller's responsibility. The `cookie_store` should be
+    /// the one the client was built with so cookie access on the requester stays in sync. A client
+    /// built with a `reqwest_cookie_store::CookieStoreMutex` can pass
+    /// `Arc::new(CookieJar::from(store))`.
+    pub fn from_client(client: Client, cookie_store: Arc<CookieJar>) -> Self {
+        Self {
+            cookie_store,
+            settings: Box::new(ClientSettings::new()),
+            client: Some(client),
+            clients: Arc::default(),
+            header_transform: None,
+            url_normalizer: None,
+            #[cfg(any(test, feature = "test-util"))]
+            mock: None,
+        }
+    }
+
+    /// Runs `transform` on the headers of every request this requester builds, after the
+    /// defaults from the settings were added and before the client adds its own.
+    pub fn set_header_transform(&mut self, transform: Option<HeaderTransform>) {
+        self.header_transform = transform;
+    }
+
+    pub(crate) fn header_transform(&self) -> Option<HeaderTransform> {
+        self.header_transform.clone()
+    }
+
+    /// Sends every request this requester builds to the url `normalizer` rewrites its url to.
+    pub fn set_url_normalizer(&mut self, normalizer: Option<Arc<dyn UrlNormalizer>>) {
+        self.url_normalizer = normalizer;
+    }
+
+    pub(crate) fn url_normalizer(&self) -> Option<Arc<dyn UrlNormalizer>> {
+        self.url_normalizer.clone()
+    }
+
+    /// Returns `url` as the url normalizer rewrites it, or unchanged without one.
+    pub(crate) fn normalize_url(&self, url: &str) -> String {
+        match &self.url_normalizer {
+            Some(normalizer) => normalizer.normalize(url),
+            None => url.to_string(),
         }
     }
 
+    /// Answers every request from `mock` instead of sending it, to test steps offline.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn set_mock_transport(&mut self, mock: Option<Arc<MockTransport>>) {
+        self.mock = mock;
+    }
+
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn mock_transport(&self) -> Option<Arc<MockTransport>> {
+        self.mock.clone()
+    }
+
     /// Builds a client with all of the internal client settings.
     /// We are unable to attach proxies, gzip, etc. with a client that has already been initialized.
     fn build_client(&self) -> Result<Client, reqwest::Error> {
-        let mut builder = Client::builder()
-            .cookie_provider(std::sync::Arc::clone(&self.cookie_store))
-            .gzip(self.settings.is_compressed());
+        self.build_client_for(&Request::default(), true)
+    }
+
+    /// Builds a client for a specific request, applying its per-request overrides on top of the
+    /// client settings. An injected client is returned as-is.
+    /// Without `decompress`, gzip bodies are left for `decode_body` to decompress.
+    /// Clients are built once per combination of settings and overrides and then reused, so
+    /// requests share their connections. Clients for a proxy set as a `Proxy` rather than from
+    /// the rotation are built anew each time, since its credentials can't be compared.
+    fn build_client_for(&self, req: &Request, decompress: bool) -> Result<Client, reqwest::Error> {
+        if let Some(client) = &self.client {
+            return Ok(client.clone());
+        }
+
+        // a proxy's Debug leaves out its credentials, so only a proxy known by its full url can
+        // tell its clients apart from those of another user of the same host
+        let proxy = match (self.settings.proxy(), self.settings.proxy_url()) {
+            (None, _) => None,
+            (Some(_), Some(url)) => Some(url.to_string()),
+            (Some(_), None) => return self.client_builder_for(req, decompress).build(),
+        };
+        let key = ClientKey {
+            proxy,
+            user_agent: self.settings.user_agent().cloned(),
+            gzip: decompress && self.settings.is_compressed(),
+            accept_invalid_certs: req.accepts_invalid_certs(),
+            cookie_store: req
+                .uses_cookies()
+                .then_some(Arc::as_ptr(&self.cookie_store) as usize),
+            host_overrides: self.settings.host_overrides().to_vec(),
+        };
+        let mut clients = self
+            .clients
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+
+        let client = self.client_builder_for(req, decompress).build()?;
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    fn client_builder_for(&self, req: &Request, decompress: bool) -> ClientBuilder {
+        let mut builder = Client::builder().gzip(decompress && self.settings.is_compressed());
+
+        // only this request's client skips verification, the settings stay strict
+        if req.accepts_invalid_certs() {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        // the cookie provider is fixed when the client is built, so skipping the store needs its own client
+        if req.uses_cookies() {
+            builder = builder.cookie_provider(std::sync::Arc::clone(&self.cookie_store));
+        }
 
         if let Some(proxy) = self.settings.proxy() {
             builder = builder.proxy(proxy.clone());
@@ -47,7 +207,11 @@ impl HttpRequester {
             builder = builder.user_agent(ua.clone());
         }
 
-        builder.build()
+        for (host, addr) in self.settings.host_overrides() {
+            builder = builder.resolve(host, *addr);
+        }
+
+        builder
     }
 
     /// Sends a request with all of the internal client settings.
@@ -79,12 +243,70 @@ impl HttpRequester {
         Ok(res)
     }
 
+    /// Sends a built request, over the configured Unix socket when one is set.
+    pub(crate) async fn send(&self, builder: RequestBuilder) -> Result<Response, SendError> {
+        #[cfg(any(test, feature = "test-util"))]
+        if let Some(mock) = &self.mock {
+            return mock.respond(builder);
+        }
+
+        #[cfg(feature = "uds")]
+        if let Some(socket) = self.settings.unix_socket() {
+            return crate::uds::send(builder, socket)
+                .await
+                .map_err(SendError::Io);
+        }
+
+        builder.send().await.map_err(SendError::Reqwest)
+    }
+
+    /// Writes the bytes of `raw` to a fresh connection and returns everything the server sends back,
+    /// bypassing reqwest entirely: no settings, cookies, proxy or decompression apply. The write
+    /// half is closed once the bytes are sent, and the response is read until the server closes
+    /// the connection, so requests should ask for `Connection: close`. Gives up after 30 seconds.
+    pub async fn send_raw(&self, raw: &RawRequest) -> io::Result<Vec<u8>> {
+        let exchange = async {
+            let stream = TcpStream::connect((raw.host.as_str(), raw.port)).await?;
+            if !raw.tls {
+                return exchange_raw(stream, &raw.bytes).await;
+            }
+
+            let connector = native_tls::TlsConnector::new().map_err(io::Error::other)?;
+            let stream = tokio_native_tls::TlsConnector::from(connector)
+                .connect(&raw.host, stream)
+                .await
+                .map_err(io::Error::other)?;
+            exchange_raw(stream, &raw.bytes).await
+        };
+
+        tokio::time::timeout(Duration::from_secs(30), exchange)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Raw request timed out"))?
+    }
+
     /// Sends a request with all of the internal client settings.
     pub fn build_reqwest(&self, req: Request) -> Result<RequestBuilder, reqwest::Error> {
-        let client = &self.build_client()?;
+        self.build_reqwest_with(req, true)
+    }
+
+    /// Like `build_reqwest`, but gzip bodies are received as they are sent over the wire, so their
+    /// size can be measured before `decode_body` decompresses them.
+    pub(crate) fn build_reqwest_undecoded(
+        &self,
+        req: Request,
+    ) -> Result<RequestBuilder, reqwest::Error> {
+        self.build_reqwest_with(req, false)
+    }
+
+    fn build_reqwest_with(
+        &self,
+        req: Request,
+        decompress: bool,
+    ) -> Result<RequestBuilder, reqwest::Error> {
+        let client = &self.build_client_for(&req, decompress)?;
 
         let mut client = client
-            .request(req.method(), req.url())
+            .request(req.method_ref().clone(), self.normalize_url(req.url()))
             .timeout(Duration::new(30, 0));
 
         match req.timeout() {
@@ -92,38 +314,391 @@ impl HttpRequester {
             None => client = client.timeout(Duration::new(30, 0)),
         }
 
-        if let Some(h) = req.headers() {
-            client = client.headers(h);
+        let mut headers = req.headers().unwrap_or_default();
+        // reqwest only adds its own Accept-Encoding when the header is missing
+        if let Some(encoding) = req.accept_encoding().filter(|e| !e.is_empty()) {
+            if let Ok(value) = HeaderValue::from_str(&encoding) {
+                headers.insert(ACCEPT_ENCODING, value);
+            }
+        }
+        // a client that doesn't decompress doesn't ask for compression either, and an empty
+        // value set by the request asks for none at all
+        if !decompress && req.accept_encoding().is_none() && !headers.contains_key(ACCEPT_ENCODING)
+        {
+            if let Some(encoding) = self.accepted_encoding() {
+                headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(encoding));
+            }
+        }
+        if req.has_text_body() && !headers.contains_key(CONTENT_TYPE) {
+            if let Some(content_type) = self.settings.text_content_type() {
+                if let Ok(value) = HeaderValue::from_str(content_type) {
+                    headers.insert(CONTENT_TYPE, value);
+                }
+            }
+        }
+        // hyper leaves the length out of empty bodies, which some servers read as chunked
+        if req.has_empty_body() && !headers.contains_key(CONTENT_LENGTH) {
+            headers.insert(CONTENT_LENGTH, HeaderValue::from_static("0"));
+        }
+        if let Some(transform) = &self.header_transform {
+            transform(&mut headers);
+        }
+        if !headers.is_empty() {
+            client = client.headers(headers);
         }
         if let Some(b) = req.body() {
             client = client.body(b);
         }
-        if let Some(f) = req.multipart() {
-            client = client.multipart(f);
+        match req.multipart_form() {
+            // reqwest always picks a random boundary, so forms with a fixed one are encoded here
+            Some(form) if form.boundary().is_some() => {
+                let boundary = form.boundary().unwrap();
+                client = client
+                    .header(
+                        CONTENT_TYPE,
+                        format!("multipart/form-data; boundary={}", boundary),
+                    )
+                    .body(form.encode(boundary));
+            }
+            Some(form) => client = client.multipart(Form::from(form.clone())),
+            None => {}
         }
 
         Ok(client)
     }
 
-    // Method to get cookies as JSON string
-    pub fn get_cookies(&self) -> Vec<u8> {
-        let mut buffer: Vec<u8> = Vec::new();
-        let store = self.cookie_store.lock().unwrap();
-        store.save_json(&mut buffer).unwrap();
-        buffer
+    // the Accept-Encoding asked for by requests whose bodies `decode_body` decompresses, none
+    // when compression is off since nothing is decoded then
+    fn accepted_encoding(&self) -> Option<&'static str> {
+        if !self.settings.is_compressed() {
+            return None;
+        }
+
+        #[cfg(feature = "zstd")]
+        if self.settings.is_zstd() {
+            return Some("gzip, zstd");
+        }
+
+        Some("gzip")
+    }
+
+    /// Decompresses a body received from a request built by `build_reqwest_undecoded`, according
+    /// to its `Content-Encoding`. Fails once the decompressed body grows past `limit`.
+    /// Bodies are returned as they are when compression is disabled.
+    pub(crate) fn decode_body(
+        &self,
+        headers: &HeaderMap,
+        body: Bytes,
+        limit: Option<usize>,
+    ) -> Result<Bytes, StepError> {
+        let encoding = headers
+            .get(CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_ascii_lowercase());
+
+        #[cfg(feature = "zstd")]
+        if self.settings.is_zstd() && self.settings.is_compressed() {
+            const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+            let zstd = match encoding.as_deref() {
+                Some(encoding) => encoding == "zstd",
+                None => body.starts_with(&ZSTD_MAGIC),
+            };
+            if zstd {
+                let decoder = zstd::stream::read::Decoder::new(body.as_ref()).map_err(|err| {
+                    StepError::Other(format!("Unable to decompress body: {}", err))
+                })?;
+                return read_decoded(decoder, limit);
+            }
+        }
+
+        let gzip = matches!(encoding.as_deref(), Some("gzip" | "x-gzip"));
+        if !gzip || !self.settings.is_compressed() {
+            return Ok(body);
+        }
+
+        read_decoded(GzDecoder::new(body.as_ref()), limit)
+    }
+
+    /// Summarizes what `builder` sends for `req`, including the headers the client adds on its
+    /// own: the user agent, `Accept`, `Accept-Encoding` and cookies from the store.
+    /// Returns `None` when the builder can't be cloned, e.g. for a multipart body without a fixed
+    /// boundary.
+    pub(crate) fn sent_request(
+        &self,
+        builder: &RequestBuilder,
+        req: &Request,
+    ) -> Option<SentRequest> {
+        let built = builder.try_clone()?.build().ok()?;
+        let mut headers = built.headers().clone();
+
+        let mut add_default = |name, value: &str| {
+            if let Ok(value) = HeaderValue::from_str(value) {
+                headers.entry(name).or_insert(value);
+            }
+        };
+        if let Some(ua) = self.settings.user_agent() {
+            add_default(USER_AGENT, ua);
+        }
+        add_default(ACCEPT, "*/*");
+        if self.settings.is_compressed() {
+            add_default(ACCEPT_ENCODING, "gzip");
+        }
+        if req.uses_cookies() {
+            let cookies = self
+                .cookies()
+                .get_request_values(built.url())
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join("; ");
+            if !cookies.is_empty() {
+                add_default(COOKIE, &cookies);
+            }
+        }
+
+        Some(SentRequest {
+            method: built.method().clone(),
+            url: built.url().to_string(),
+            headers,
+        })
+    }
+
+    pub(crate) fn cookie_store(&self) -> Arc<CookieJar> {
+        Arc::clone(&self.cookie_store)
+    }
+
+    /// Swaps the cookie store used by the requests built from now on.
+    /// An injected client keeps the store it was built with.
+    pub(crate) fn set_cookie_store(&mut self, cookie_store: Arc<CookieJar>) {
+        self.cookie_store = cookie_store;
+    }
+
+    /// Locks the cookie store. A lock poisoned by a thread that panicked while holding it is
+    /// recovered rather than propagated, so one panic doesn't break every later cookie access.
+    /// The store itself stays usable since no cookie operation leaves it half updated.
+    pub(crate) fn cookies(&self) -> MutexGuard<'_, CookieStore> {
+        self.cookie_store
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Gets the cookies as a JSON string.
+    /// Fails instead of panicking when the cookies can't be serialized.
+    /// Blocks while another thread holds the cookie store, see `try_get_cookies`.
+    pub fn get_cookies(&self) -> Result<Vec<u8>, CookieError> {
+        save_cookies(&self.cookies())
+    }
+
+    /// Gets the cookies as a JSON string like `get_cookies`, or `None` instead of waiting while
+    /// another thread holds the cookie store or when the cookies can't be serialized.
+    pub fn try_get_cookies(&self) -> Option<Vec<u8>> {
+        let store = match self.cookie_store.try_lock() {
+            Ok(store) => store,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        save_cookies(&store).ok()
+    }
+
+    /// Returns the name and value of every cookie that would be sent with a request to `url`,
+    /// matching domain, path, secure flag and expiry. Empty when `url` can't be parsed.
+    pub fn cookies_for(&self, url: &str) -> Vec<(String, String)> {
+        let url = match Url::parse(url) {
+            Ok(url) => url,
+            Err(_) => return Vec::new(),
+        };
+
+        self.cookies()
+            .get_request_values(&url)
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Adds session cookies to the store, given as `(name, value, domain)`, e.g. to start from a
+    /// session logged in elsewhere. Each cookie is sent on every path of `domain`, and a leading
+    /// `.` in the domain also sends it to its subdomains. Stops at the first invalid cookie.
+    pub fn set_cookies(&self, cookies: Vec<(String, String, String)>) -> Result<(), CookieError> {
+        let mut store = self.cookies();
+
+        for (name, value, domain) in cookies {
+            let host = domain.trim_start_matches('.');
+            let mut cookie = format!("{}={}; Path=/", name, value);
+            if domain.starts_with('.') {
+                cookie.push_str(&format!("; Domain={}", host));
+            }
+
+            let url = Url::parse(&format!("http://{}/", host))
+                .map_err(|err| CookieError::Invalid(format!("{}: {}", domain, err)))?;
+            store
+                .parse(&cookie, &url)
+                .map_err(|err| CookieError::Invalid(format!("{}: {}", name, err)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Loads cookies exported in the Netscape `cookies.txt` format (as written by curl and most
+    /// browser extensions) into the cookie store.
+    ///
+    /// Each line holds seven tab-separated fields: domain, include subdomains, path, secure,
+    /// expiry (unix seconds, `0` for session cookies), name and value. Blank lines and comments
+    /// are skipped, except for the `#HttpOnly_` prefix which marks the cookie as HTTP only.
+    /// Cookies that have already expired are ignored.
+    pub fn load_cookies_txt(&self, path: &Path) -> io::Result<()> {
+        let contents = std::fs::read_to_string(path)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        let mut store = self.cookies();
+
+        for (number, line) in contents.lines().enumerate() {
+            let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                Some(rest) => (rest, true),
+                None => (line, false),
+            };
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |reason: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("cookies.txt line {}: {}", number + 1, reason),
+                )
+            };
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [domain, include_subdomains, cookie_path, secure, expiry, name, value] = fields[..]
+            else {
+                return Err(invalid("expected 7 tab-separated fields"));
+            };
+
+            let secure = secure.eq_ignore_ascii_case("TRUE");
+            let expiry: u64 = expiry.parse().map_err(|_| invalid("invalid expiry"))?;
+            if expiry != 0 && expiry <= now {
+                continue;
+            }
+
+            let host = domain.trim_start_matches('.');
+            let mut cookie = format!("{}={}; Path={}", name, value, cookie_path);
+            if include_subdomains.eq_ignore_ascii_case("TRUE") {
+                cookie.push_str(&format!("; Domain={}", host));
+            }
+            if expiry != 0 {
+                cookie.push_str(&format!("; Max-Age={}", expiry - now));
+            }
+            if secure {
+                cookie.push_str("; Secure");
+            }
+            if http_only {
+                cookie.push_str("; HttpOnly");
+            }
+
+            let scheme = if secure { "https" } else { "http" };
+            let url = Url::parse(&format!("{}://{}{}", scheme, host, cookie_path))
+                .map_err(|_| invalid("invalid domain or path"))?;
+            store
+                .parse(&cookie, &url)
+                .map_err(|err| invalid(&err.to_string()))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An error from sending a request through reqwest or through an alternative transport.
+#[derive(Debug)]
+pub(crate) enum SendError {
+    Reqwest(reqwest::Error),
+    #[cfg_attr(
+        not(any(test, feature = "uds", feature = "test-util")),
+        allow(dead_code)
+    )]
+    Io(io::Error),
+}
+
+impl SendError {
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            SendError::Reqwest(err) => err.is_timeout(),
+            SendError::Io(err) => err.kind() == io::ErrorKind::TimedOut,
+        }
+    }
+
+    pub fn timeout_phase(&self) -> TimeoutPhase {
+        match self {
+            SendError::Reqwest(err) => TimeoutPhase::from_reqwest(err, false),
+            SendError::Io(_) => TimeoutPhase::Overall,
+        }
+    }
+
+    pub fn into_boxed(self) -> Box<dyn std::error::Error> {
+        match self {
+            SendError::Reqwest(err) => Box::new(err),
+            SendError::Io(err) => Box::new(err),
+        }
+    }
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Reqwest(err) => write!(f, "{}", err),
+            SendError::Io(err) => write!(f, "{}", err),
+        }
     }
 }
 
-fn new_cookie_store() -> Arc<CookieStoreMutex> {
-    let cookie_store = CookieStoreMutex::new(CookieStore::new(None));
+pub(crate) fn new_cookie_store() -> Arc<CookieJar> {
+    let cookie_store = CookieJar::new(CookieStore::new(None));
     Arc::new(cookie_store)
 }
 
+fn save_cookies(store: &CookieStore) -> Result<Vec<u8>, CookieError> {
+    let mut buffer: Vec<u8> = Vec::new();
+    store
+        .save_json(&mut buffer)
+        .map_err(|err| CookieError::Serialization(err.to_string()))?;
+    Ok(buffer)
+}
+
+// reads a decompressed body, failing once it grows past `limit`
+fn read_decoded(decoder: impl Read, limit: Option<usize>) -> Result<Bytes, StepError> {
+    // read one byte past the limit to tell a body of exactly `limit` bytes from a bigger one
+    let cap = limit.map_or(u64::MAX, |limit| limit as u64 + 1);
+    let mut decoded = Vec::new();
+    decoder
+        .take(cap)
+        .read_to_end(&mut decoded)
+        .map_err(|err| StepError::Other(format!("Unable to decompress body: {}", err)))?;
+
+    match limit {
+        Some(limit) if decoded.len() > limit => Err(StepError::DecompressionLimitExceeded(limit)),
+        _ => Ok(Bytes::from(decoded)),
+    }
+}
+
+// writes the request, closes the write half and reads the response until the connection closes
+async fn exchange_raw<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    bytes: &[u8],
+) -> io::Result<Vec<u8>> {
+    stream.write_all(bytes).await?;
+    stream.shutdown().await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    Ok(response)
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::hdr;
     use crate::request::{MimicBody, MimicForm};
-    use reqwest::header::HeaderValue;
+    use crate::test_server::{TestResponse, TestServer};
     use reqwest::Proxy;
+    use reqwest_cookie_store::CookieStoreMutex;
 
     use super::*;
 
@@ -203,6 +778,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn it_should_only_accept_invalid_certs_for_requests_asking_for_it() {
+        let http = HttpRequester::new();
+        let strict = Request::new(Method::GET, "https://self-signed.example".to_string());
+        let relaxed = strict.clone().danger_accept_invalid_certs(true);
+
+        let builder = format!("{:?}", http.client_builder_for(&relaxed, true));
+        assert!(
+            builder.contains("danger_accept_invalid_certs"),
+            "{}",
+            builder
+        );
+
+        let builder = format!("{:?}", http.client_builder_for(&strict, true));
+        assert!(
+            !builder.contains("danger_accept_invalid_certs"),
+            "{}",
+            builder
+        );
+        assert!(http.build_reqwest(relaxed).is_ok());
+    }
+
     #[test]
     fn it_should_build_a_request() {
         let http = HttpRequester::new();
@@ -253,6 +850,92 @@ mod tests {
         }
     }
 
+    #[test]
+    fn it_should_build_a_multipart_request_with_a_fixed_boundary() {
+        let http = HttpRequester::new();
+        let form = MimicForm::new(
+            vec![("name".to_string(), "value".to_string())],
+            vec![("file".to_string(), vec![1, 2, 3])],
+        )
+        .with_boundary("mimicr-boundary".to_string());
+        let req = Request::new(Method::POST, "https://google.com".to_string()).with_multipart(form);
+
+        let built = http.build_reqwest(req).unwrap().build().unwrap();
+        assert_eq!(
+            built.headers()[CONTENT_TYPE],
+            "multipart/form-data; boundary=mimicr-boundary"
+        );
+        assert_eq!(
+            built.body().unwrap().as_bytes().unwrap(),
+            b"--mimicr-boundary\r\n\
+            Content-Disposition: form-data; name=\"name\"\r\n\r\n\
+            value\r\n\
+            --mimicr-boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"\r\n\r\n\
+            \x01\x02\x03\r\n\
+            --mimicr-boundary--\r\n"
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn it_should_build_a_multipart_request_with_a_json_part() {
+        let http = HttpRequester::new();
+        let form = MimicForm::new(vec![], vec![("image".to_string(), vec![0xFF, 0xD8])])
+            .json_part("metadata", &serde_json::json!({"title": "cat"}))
+            .unwrap();
+        assert!(http
+            .build_reqwest(
+                Request::new(Method::POST, "https://google.com".to_string())
+                    .with_multipart(form.clone())
+            )
+            .is_ok());
+
+        let req = Request::new(Method::POST, "https://google.com".to_string())
+            .with_multipart(form.with_boundary("mimicr-boundary".to_string()));
+        let built = http.build_reqwest(req).unwrap().build().unwrap();
+        let body = String::from_utf8_lossy(built.body().unwrap().as_bytes().unwrap()).to_string();
+
+        assert!(
+            body.contains(
+                "Content-Disposition: form-data; name=\"metadata\"\r\n\
+            Content-Type: application/json\r\n\r\n\
+            {\"title\":\"cat\"}\r\n"
+            ),
+            "{}",
+            body
+        );
+        assert!(body.contains("name=\"image\"\r\n\r\n"), "{}", body);
+    }
+
+    #[test]
+    fn it_should_send_text_bodies_with_the_default_content_type() {
+        let mut http = HttpRequester::new();
+        let text = Request::new(Method::POST, "https://google.com".to_string())
+            .with_body(MimicBody::from_text("hello".to_string()));
+        let content_type = |http: &HttpRequester, req: Request| {
+            let built = http.build_reqwest(req).unwrap().build().unwrap();
+            built.headers().get(CONTENT_TYPE).cloned()
+        };
+
+        assert_eq!(
+            content_type(&http, text.clone()).unwrap(),
+            "text/plain; charset=utf-8"
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/xml"));
+        let xml = text.clone().with_headers(headers);
+        assert_eq!(content_type(&http, xml).unwrap(), "application/xml");
+
+        let bytes = Request::new(Method::POST, "https://google.com".to_string())
+            .with_body(MimicBody::from_bytes(b"hello".to_vec()));
+        assert_eq!(content_type(&http, bytes), None);
+
+        http.settings.set_text_content_type(None);
+        assert_eq!(content_type(&http, text), None);
+    }
+
     #[test]
     fn it_should_build_a_request_using_default() {
         let http = HttpRequester::new();
@@ -280,4 +963,378 @@ mod tests {
             Err(_) => panic!("invalid"),
         }
     }
+
+    #[tokio::test]
+    async fn it_should_resolve_hosts_through_the_overrides() {
+        let server = TestServer::ok("overridden").await;
+        let port = server.addr().port();
+
+        let mut http = HttpRequester::new();
+        http.settings
+            .add_host_override("example.com", "10.0.0.1:80".parse().unwrap())
+            .add_host_override("example.com", server.addr())
+            .add_host_override("api.example.com", server.addr());
+        assert_eq!(http.settings.host_overrides().len(), 2);
+
+        for host in ["example.com", "api.example.com"] {
+            let url = format!("http://{}:{}/", host, port);
+            let res = http.req(Method::GET, url, None, None).await.unwrap();
+            assert_eq!(res.text().await.unwrap(), "overridden");
+        }
+
+        let requests = server.requests();
+        assert_eq!(
+            requests[0].header("Host"),
+            Some(format!("example.com:{}", port).as_str())
+        );
+        assert_eq!(
+            requests[1].header("Host"),
+            Some(format!("api.example.com:{}", port).as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn it_should_evict_failing_proxies_from_the_rotation() {
+        let proxy = TestServer::ok("proxied").await;
+        let dead = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let dead_url = format!("http://{}", dead.local_addr().unwrap());
+        drop(dead);
+        let live_url = proxy.url("");
+
+        let mut settings = ClientSettings::new();
+        settings
+            .add_rotating_proxy(&dead_url)
+            .unwrap()
+            .add_rotating_proxy(&live_url)
+            .unwrap()
+            .set_proxy_check_url("http://health.invalid/");
+        assert_eq!(settings.active_proxies().len(), 2);
+
+        settings.check_proxies().await;
+
+        assert_eq!(settings.active_proxies(), vec![live_url.clone()]);
+        assert_eq!(settings.proxy_failures(&dead_url), Some(1));
+        assert_eq!(settings.proxy_failures(&live_url), Some(0));
+        assert_eq!(proxy.requests()[0].target, "http://health.invalid/");
+        for _ in 0..3 {
+            assert_eq!(
+                format!("{:?}", settings.next_rotating_proxy().unwrap()),
+                format!("{:?}", Proxy::all(&live_url).unwrap())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_send_a_urlencoded_form() {
+        let server = TestServer::ok("ok").await;
+        let http = HttpRequester::new();
+        let req = Request::new(Method::POST, server.url("/login")).with_form(vec![
+            ("user".to_string(), "alice".to_string()),
+            ("note".to_string(), "a&b c=d".to_string()),
+        ]);
+
+        http.build_reqwest(req).unwrap().send().await.unwrap();
+
+        let recorded = &server.requests()[0];
+        assert_eq!(
+            recorded.header("Content-Type"),
+            Some("application/x-www-form-urlencoded")
+        );
+        assert_eq!(recorded.body, b"user=alice&note=a%26b+c%3Dd");
+    }
+
+    #[tokio::test]
+    async fn it_should_send_an_empty_body_with_a_zero_content_length() {
+        let server = TestServer::ok("ok").await;
+        let http = HttpRequester::new();
+        let req = Request::new(Method::POST, server.url("/ping")).with_body(MimicBody::empty());
+
+        http.build_reqwest(req).unwrap().send().await.unwrap();
+
+        let recorded = &server.requests()[0];
+        assert_eq!(recorded.header("Content-Length"), Some("0"));
+        assert_eq!(recorded.header("Transfer-Encoding"), None);
+        assert!(recorded.body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_send_requests_with_an_injected_client() {
+        let server = TestServer::ok("hello").await;
+
+        let cookie_store = new_cookie_store();
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert("X-Custom-Client", HeaderValue::from_static("yes"));
+        let client = Client::builder()
+            .cookie_provider(cookie_store.clone())
+            .default_headers(default_headers)
+            .build()
+            .unwrap();
+
+        let http = HttpRequester::from_client(client, cookie_store);
+        let res = http
+            .req(Method::GET, server.url("/"), None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(res.text().await.unwrap(), "hello");
+        assert_eq!(
+            server.requests()[0].header("X-Custom-Client"),
+            Some("yes"),
+            "the request should be sent by the injected client"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_should_share_the_cookies_of_a_client_built_with_a_cookie_store_mutex() {
+        let server =
+            TestServer::start(|_| TestResponse::ok("ok").header("Set-Cookie", "session=abc")).await;
+
+        let cookie_store = Arc::new(CookieStoreMutex::default());
+        let client = Client::builder()
+            .cookie_provider(cookie_store.clone())
+            .build()
+            .unwrap();
+
+        let http = HttpRequester::from_client(client, Arc::new(CookieJar::from(cookie_store)));
+        http.req(Method::GET, server.url("/"), None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            http.cookies_for(&server.url("/")),
+            vec![("session".to_string(), "abc".to_string())]
+        );
+    }
+
+    #[test]
+    fn it_should_build_a_request_with_an_explicit_accept_encoding() {
+        let http = HttpRequester::new();
+        let req = Request::new(Method::GET, "https://test.com".to_string())
+            .with_headers(hdr!("Accept-Encoding: gzip"))
+            .with_accept_encoding("gzip, deflate, br, zstd");
+
+        let built = http.build_reqwest(req).unwrap().build().unwrap();
+        assert_eq!(
+            built.headers().get_all(ACCEPT_ENCODING).iter().count(),
+            1,
+            "the explicit value should replace any existing header"
+        );
+        assert_eq!(
+            built.headers().get(ACCEPT_ENCODING).unwrap(),
+            "gzip, deflate, br, zstd"
+        );
+    }
+
+    #[test]
+    fn get_cookies_should_serialize_an_empty_store() {
+        let http = HttpRequester::new();
+        assert!(http.get_cookies().unwrap().is_empty());
+    }
+
+    #[test]
+    fn try_get_cookies_should_not_wait_for_a_held_store() {
+        let http = HttpRequester::new();
+
+        let guard = http.cookie_store.lock().unwrap();
+        assert_eq!(http.try_get_cookies(), None);
+        drop(guard);
+
+        assert_eq!(http.try_get_cookies(), Some(http.get_cookies().unwrap()));
+    }
+
+    #[test]
+    fn cookie_access_should_recover_from_a_poisoned_lock() {
+        let http = HttpRequester::new();
+        let store = http.cookie_store.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = store.lock().unwrap();
+            panic!("poisoning the cookie store");
+        })
+        .join();
+        assert!(http.cookie_store.lock().is_err());
+
+        let url = Url::parse("https://example.com/").unwrap();
+        http.cookies()
+            .parse("session=abc; Max-Age=3600", &url)
+            .unwrap();
+
+        let cookies = String::from_utf8(http.get_cookies().unwrap()).unwrap();
+        assert!(cookies.contains("session"), "{}", cookies);
+    }
+
+    #[test]
+    fn cookies_for_should_only_return_the_cookies_sent_to_the_url() {
+        let http = HttpRequester::new();
+        let shop = Url::parse("https://shop.example/").unwrap();
+        let blog = Url::parse("https://blog.example/").unwrap();
+        {
+            let mut store = http.cookies();
+            store.parse("cart=3", &shop).unwrap();
+            store.parse("admin=1; Path=/admin", &shop).unwrap();
+            store.parse("theme=dark", &blog).unwrap();
+        }
+
+        let pair = |name: &str, value: &str| (name.to_string(), value.to_string());
+        assert_eq!(
+            http.cookies_for("https://shop.example/"),
+            [pair("cart", "3")]
+        );
+        let mut admin = http.cookies_for("https://shop.example/admin/users");
+        admin.sort();
+        assert_eq!(admin, [pair("admin", "1"), pair("cart", "3")]);
+        assert_eq!(
+            http.cookies_for("https://blog.example/post"),
+            [pair("theme", "dark")]
+        );
+        assert!(http.cookies_for("https://other.example/").is_empty());
+        assert!(http.cookies_for("not a url").is_empty());
+    }
+
+    #[tokio::test]
+    async fn send_raw_should_write_the_bytes_as_they_are() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            socket.read_to_end(&mut received).await.unwrap();
+            socket.write_all(&received).await.unwrap();
+        });
+
+        let bytes = b"GARBAGE /\0 HTTP/9.9\r\nX: \xff\r\n\r\n".to_vec();
+        let raw = RawRequest::new("127.0.0.1", port, bytes.clone());
+        let echoed = HttpRequester::new().send_raw(&raw).await.unwrap();
+
+        assert_eq!(echoed, bytes);
+    }
+
+    #[tokio::test]
+    async fn it_should_load_cookies_from_a_netscape_cookies_txt() {
+        let server = TestServer::ok("ok").await;
+        let path = std::env::temp_dir().join(format!("mimicr-cookies-{}.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            "# Netscape HTTP Cookie File\n\
+             # This is a generated file! Do not edit.\n\
+             \n\
+             127.0.0.1\tFALSE\t/\tFALSE\t0\tsession\tabc\n\
+             #HttpOnly_127.0.0.1\tFALSE\t/\tFALSE\t4102444800\ttoken\txyz\n\
+             127.0.0.1\tFALSE\t/\tFALSE\t1\texpired\told\n\
+             .example.com\tTRUE\t/\tTRUE\t0\tother\tnope\n",
+        )
+        .unwrap();
+
+        let http = HttpRequester::new();
+        http.load_cookies_txt(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let req = Request::new(Method::GET, server.url("/"));
+        http.build_reqwest(req).unwrap().send().await.unwrap();
+
+        let cookie = server.requests()[0].header("Cookie").unwrap().to_string();
+        assert!(cookie.contains("session=abc"));
+        assert!(cookie.contains("token=xyz"));
+        assert!(!cookie.contains("expired"));
+        assert!(!cookie.contains("other"));
+    }
+
+    #[tokio::test]
+    async fn it_should_send_cookies_set_in_a_batch_to_matching_urls() {
+        let server = TestServer::ok("ok").await;
+        let http = HttpRequester::new();
+        let cookie = |name: &str, value: &str, domain: &str| {
+            (name.to_string(), value.to_string(), domain.to_string())
+        };
+
+        http.set_cookies(vec![
+            cookie("session", "abc", "127.0.0.1"),
+            cookie("lang", "en", "127.0.0.1"),
+            cookie("shared", "1", ".example.com"),
+        ])
+        .unwrap();
+
+        let req = Request::new(Method::GET, server.url("/deep/path"));
+        http.build_reqwest(req).unwrap().send().await.unwrap();
+
+        let sent = server.requests()[0].header("Cookie").unwrap().to_string();
+        assert!(sent.contains("session=abc"));
+        assert!(sent.contains("lang=en"));
+        assert!(!sent.contains("shared"));
+        assert_eq!(
+            http.cookies_for("https://api.example.com/"),
+            [("shared".to_string(), "1".to_string())]
+        );
+        assert!(http.cookies_for("https://example.org/").is_empty());
+
+        let err = http
+            .set_cookies(vec![cookie("bad", "1", "not a domain")])
+            .unwrap_err();
+        assert!(matches!(err, CookieError::Invalid(_)));
+    }
+
+    #[test]
+    fn it_should_reject_malformed_cookies_txt_lines() {
+        let path =
+            std::env::temp_dir().join(format!("mimicr-bad-cookies-{}.txt", std::process::id()));
+        std::fs::write(&path, "127.0.0.1\tFALSE\t/\n").unwrap();
+
+        let err = HttpRequester::new().load_cookies_txt(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn it_should_not_send_store_cookies_for_requests_without_cookies() {
+        let server = TestServer::ok("ok").await;
+        let http = HttpRequester::new();
+        let url = Url::parse(&server.url("/")).unwrap();
+        http.cookie_store
+            .lock()
+            .unwrap()
+            .parse("session=abc", &url)
+            .unwrap();
+
+        let req = Request::new(Method::GET, server.url("/"));
+        http.build_reqwest(req).unwrap().send().await.unwrap();
+
+        let req = Request::new(Method::GET, server.url("/")).without_cookies();
+        http.build_reqwest(req).unwrap().send().await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("Cookie"), Some("session=abc"));
+        assert_eq!(requests[1].header("Cookie"), None);
+    }
+
+    #[tokio::test]
+    async fn it_should_send_only_the_explicit_cookies() {
+        let server = TestServer::start(|_| {
+            TestResponse::ok("ok").header("Set-Cookie", "tracking=xyz; Path=/")
+        })
+        .await;
+        let http = HttpRequester::new();
+        let url = Url::parse(&server.url("/")).unwrap();
+        http.cookie_store
+            .lock()
+            .unwrap()
+            .parse("session=abc", &url)
+            .unwrap();
+
+        let req = Request::new(Method::GET, server.url("/")).cookies_explicit_only(vec![
+            ("token".to_string(), "t1".to_string()),
+            ("lang".to_string(), "en".to_string()),
+        ]);
+        http.build_reqwest(req).unwrap().send().await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("Cookie"), Some("token=t1; lang=en"));
+        let stored: Vec<String> = http
+            .cookie_store
+            .lock()
+            .unwrap()
+            .iter_any()
+            .map(|cookie| cookie.name().to_string())
+            .collect();
+        assert_eq!(stored, ["session"]);
+    }
 }
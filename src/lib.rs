@@ -1,15 +1,43 @@
-pub use client_settings::ClientSettings;
-pub use context::Context;
-pub use errors::StepError;
-pub use http_requester::HttpRequester;
-pub use request::Request;
-pub use steps::Stepable;
-pub use worker::Worker;
+pub use client_settings::{
+    ClientSettings, ContentLengthCheck, UserAgentRotation, BROWSER_USER_AGENTS,
+};
+pub use context::{Context, ErrorHandler};
+pub use cookies::CookieJar;
+pub use errors::{ConvertError, CookieError, RequestError, StepError, TimeoutPhase};
+pub use http_requester::{HeaderTransform, HttpRequester};
+#[cfg(any(test, feature = "test-util"))]
+pub use mock::{MockResponse, MockTransport};
+pub use normalize::{DefaultUrlNormalizer, UrlNormalizer};
+pub use report::{Metrics, RunReport, StepOutcome};
+pub use request::{JsonKind, RawRequest, Request, SentRequest};
+pub use stats::{AdaptiveTimeout, Latency, RunStats, SizeStats, StepStats};
+pub use steps::{FlowControl, Stepable, PROGRESS_INTERVAL};
+#[cfg(feature = "sse")]
+pub use stream::SseEvent;
+#[cfg(feature = "wire-log")]
+pub use wire::{WireEvent, WireLogger};
+pub use worker::{RunHook, Worker};
 
+mod cache;
 mod client_settings;
 mod context;
+mod cookies;
 mod errors;
 mod http_requester;
+mod limits;
+#[cfg(any(test, feature = "test-util"))]
+mod mock;
+mod normalize;
+mod report;
 mod request;
+mod stats;
 mod steps;
+#[cfg(any(feature = "json", feature = "sse"))]
+mod stream;
+#[cfg(test)]
+mod test_server;
+#[cfg(feature = "uds")]
+mod uds;
+#[cfg(feature = "wire-log")]
+mod wire;
 mod worker;
@@ -0,0 +1,68 @@
+//! Caps on the requests in flight, see `Worker::with_concurrency_limit`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use reqwest::Url;
+use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
+
+/// The concurrency limits every send path waits on, shared by a worker with its forks and lanes.
+#[derive(Clone, Default)]
+pub(crate) struct Limits {
+    /// Caps the number of requests in flight at once.
+    overall: Option<Arc<Semaphore>>,
+    /// Caps the number of requests in flight to each host.
+    per_host: Option<Arc<HostLimiter>>,
+}
+
+// a semaphore per host, created the first time a request goes to it
+struct HostLimiter {
+    limit: usize,
+    hosts: std::sync::Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl HostLimiter {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            hosts: std::sync::Mutex::default(),
+        }
+    }
+
+    fn semaphore(&self, host: &str) -> Arc<Semaphore> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let semaphore = hosts
+            .entry(host.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.limit)));
+        Arc::clone(semaphore)
+    }
+}
+
+impl Limits {
+    pub fn set_overall(&mut self, limit: usize) {
+        self.overall = Some(Arc::new(Semaphore::new(limit)));
+    }
+
+    pub fn set_per_host(&mut self, limit: usize) {
+        self.per_host = Some(Arc::new(HostLimiter::new(limit)));
+    }
+
+    /// Waits for the permits of the host of `url` and then for the overall ones, in that order
+    /// so a request waiting on a busy host doesn't hold up the others. The request may be sent
+    /// while the permits are held.
+    pub async fn acquire(&self, url: &str) -> Result<Vec<OwnedSemaphorePermit>, AcquireError> {
+        let mut permits = Vec::new();
+
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string));
+        if let (Some(limiter), Some(host)) = (&self.per_host, host) {
+            permits.push(limiter.semaphore(&host).acquire_owned().await?);
+        }
+        if let Some(limiter) = &self.overall {
+            permits.push(limiter.clone().acquire_owned().await?);
+        }
+
+        Ok(permits)
+    }
+}
@@ -0,0 +1,219 @@
+//! Canned responses for testing steps offline.
+//!
+//! A `MockTransport` set on a worker answers every request from the responses registered for
+//! its url, so whole pipelines run deterministically without a network. Requests for a url
+//! without a registered response fail like a connection error would.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+
+use reqwest::{Method, RequestBuilder, Response, Url};
+
+use crate::http_requester::SendError;
+
+/// A canned response returned by a `MockTransport`.
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl MockResponse {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// A `200 OK` response with the given body.
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self::new(200).with_body(body)
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+/// Answers requests with the responses registered for their url instead of sending them.
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    responses: Mutex<HashMap<String, MockResponse>>,
+    requests: Mutex<Vec<(Method, String)>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers every request for `url`, whatever its method, with `response`.
+    /// Registering a url again replaces its response.
+    pub fn register(&self, url: &str, response: MockResponse) -> &Self {
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(normalize(url), response);
+        self
+    }
+
+    /// Returns the method and url of every request received so far, in order.
+    pub fn requests(&self) -> Vec<(Method, String)> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub(crate) fn respond(&self, builder: RequestBuilder) -> Result<Response, SendError> {
+        let request = builder.build().map_err(SendError::Reqwest)?;
+        let url = request.url().to_string();
+        self.requests
+            .lock()
+            .unwrap()
+            .push((request.method().clone(), url.clone()));
+
+        let mock = match self.responses.lock().unwrap().get(&url) {
+            Some(mock) => mock.clone(),
+            None => {
+                return Err(SendError::Io(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No mock response registered for {}", url),
+                )))
+            }
+        };
+
+        let mut response = http::Response::builder().status(mock.status);
+        for (name, value) in &mock.headers {
+            response = response.header(name, value);
+        }
+        let response = response
+            .body(mock.body)
+            .map_err(|err| SendError::Io(io::Error::other(err)))?;
+
+        Ok(Response::from(response))
+    }
+}
+
+// parses the url so it matches the form reqwest sends, e.g. with a trailing slash
+fn normalize(url: &str) -> String {
+    Url::parse(url)
+        .map(|url| url.to_string())
+        .unwrap_or_else(|_| url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use reqwest::Method;
+
+    use super::*;
+    use crate::{Context, Request, StepError, Stepable, Worker};
+
+    struct Robots {
+        seen: Arc<Mutex<Option<String>>>,
+    }
+
+    impl Stepable for Robots {
+        fn name(&self) -> String {
+            String::from("Robots")
+        }
+
+        fn on_request(&self) -> Request {
+            Request::new(Method::GET, "https://google.com".to_string())
+        }
+
+        fn on_success(&self, ctx: &mut Context) {
+            *self.seen.lock().unwrap() = Some(ctx.body_text().unwrap());
+        }
+
+        fn on_error(&self, _ctx: &mut Context, _err: StepError) {}
+
+        fn on_timeout(&self, _ctx: &mut Context) {}
+    }
+
+    #[tokio::test]
+    async fn it_should_run_a_step_against_a_registered_mock() {
+        let mock = Arc::new(MockTransport::new());
+        mock.register(
+            "https://google.com",
+            MockResponse::ok("User-agent: *").with_header("Content-Type", "text/plain"),
+        );
+        let seen = Arc::new(Mutex::new(None));
+
+        let mut worker = Worker::new();
+        worker.set_mock_transport(mock.clone());
+        worker.add_step(Robots { seen: seen.clone() });
+        worker.try_step("Robots").await.unwrap();
+
+        assert_eq!(seen.lock().unwrap().as_deref(), Some("User-agent: *"));
+        assert_eq!(worker.context().get_status_code(), Some(200));
+        assert_eq!(
+            worker.context().get_response_headers().unwrap()["Content-Type"],
+            "text/plain"
+        );
+        assert_eq!(
+            mock.requests(),
+            vec![(Method::GET, "https://google.com/".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_should_expose_the_trailers_of_a_grpc_web_response() {
+        let trailer = b"grpc-status: 0\r\ngrpc-message: done\r\n";
+        let mut body = vec![0x00, 0, 0, 0, 2, 0x08, 0x01];
+        body.extend_from_slice(&[0x80, 0, 0, 0, trailer.len() as u8]);
+        body.extend_from_slice(trailer);
+
+        let mock = Arc::new(MockTransport::new());
+        mock.register(
+            "https://google.com",
+            MockResponse::ok(body).with_header("Content-Type", "application/grpc-web+proto"),
+        );
+
+        let mut worker = Worker::new();
+        worker.set_mock_transport(mock.clone());
+        worker.add_step(Robots {
+            seen: Arc::new(Mutex::new(None)),
+        });
+        worker.try_step("Robots").await.unwrap();
+
+        let trailers = worker.context().trailers().unwrap();
+        assert_eq!(trailers["grpc-status"], "0");
+        assert_eq!(trailers["grpc-message"], "done");
+
+        mock.register(
+            "https://google.com",
+            MockResponse::ok("plain").with_header("Content-Type", "text/plain"),
+        );
+        worker.try_step("Robots").await.unwrap();
+        assert!(worker.context().trailers().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_requests_without_a_registered_mock() {
+        let mock = Arc::new(MockTransport::new());
+        mock.register("https://google.com", MockResponse::new(503));
+        let seen = Arc::new(Mutex::new(None));
+
+        let mut worker = Worker::new();
+        worker.set_mock_transport(mock.clone());
+        worker.add_step(Robots { seen: seen.clone() });
+
+        let err = worker.try_step("Robots").await.unwrap_err();
+        assert!(err.to_string().contains("503"));
+
+        worker.set_mock_transport(Arc::new(MockTransport::new()));
+        let err = worker.try_step("Robots").await.unwrap_err();
+        assert!(err.to_string().contains("No mock response registered"));
+        assert!(seen.lock().unwrap().is_none());
+    }
+}
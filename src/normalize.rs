@@ -0,0 +1,60 @@
+//! Canonical forms of request urls, see `Worker::set_url_normalizer`.
+
+use reqwest::Url;
+
+/// Rewrites the url of every request before it is sent, so urls naming the same resource are
+/// sent, cached and compared alike.
+pub trait UrlNormalizer: Send + Sync {
+    fn normalize(&self, url: &str) -> String;
+}
+
+/// Lowercases the scheme and host, drops the port when it is the scheme's default and sorts the
+/// query parameters by name, keeping the order of repeated ones. Urls that can't be parsed are
+/// left as is.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultUrlNormalizer;
+
+impl UrlNormalizer for DefaultUrlNormalizer {
+    fn normalize(&self, url: &str) -> String {
+        // parsing already lowercases the scheme and host and drops a default port
+        let mut parsed = match Url::parse(url) {
+            Ok(parsed) => parsed,
+            Err(_) => return url.to_string(),
+        };
+
+        if let Some(query) = parsed.query().filter(|query| !query.is_empty()) {
+            // sorting the raw pairs keeps their encoding as it was
+            let mut pairs: Vec<&str> = query.split('&').collect();
+            pairs.sort_by_key(|pair| pair.split('=').next().unwrap_or_default());
+            let sorted = pairs.join("&");
+            parsed.set_query(Some(&sorted));
+        }
+
+        parsed.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_lowercase_the_host_and_drop_the_default_port() {
+        let url = DefaultUrlNormalizer.normalize("HTTPS://Example.COM:443/Path");
+        assert_eq!(url, "https://example.com/Path");
+
+        let url = DefaultUrlNormalizer.normalize("http://Example.com:8080/");
+        assert_eq!(url, "http://example.com:8080/");
+    }
+
+    #[test]
+    fn it_should_sort_the_query_parameters_by_name() {
+        let url = DefaultUrlNormalizer.normalize("http://example.com/?b=2&a=1&b=1&c=%20");
+        assert_eq!(url, "http://example.com/?a=1&b=2&b=1&c=%20");
+    }
+
+    #[test]
+    fn it_should_leave_unparsable_urls_as_is() {
+        assert_eq!(DefaultUrlNormalizer.normalize("/relative"), "/relative");
+    }
+}
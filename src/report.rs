@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use crate::StepError;
+
+/// The result of a pipeline run, as returned by `Worker::run`.
+#[derive(Debug, Clone, Default)]
+pub struct RunReport {
+    pub steps_run: usize,
+    pub successes: usize,
+    pub failures: usize,
+    /// Wall-clock time of the whole run.
+    pub total_elapsed: Duration,
+    /// One outcome per executed step, in execution order.
+    pub per_step: Vec<StepOutcome>,
+}
+
+impl RunReport {
+    pub(crate) fn push(&mut self, outcome: StepOutcome) {
+        self.steps_run += 1;
+        if outcome.is_success() {
+            self.successes += 1;
+        } else {
+            self.failures += 1;
+        }
+        self.per_step.push(outcome);
+    }
+}
+
+/// The outcome of a single step execution.
+#[derive(Debug, Clone)]
+pub struct StepOutcome {
+    pub name: String,
+    /// The response status code, if a response was received.
+    pub status: Option<u16>,
+    /// Wall-clock time of the step, including reading the body and running its hooks.
+    pub elapsed: Duration,
+    pub error: Option<StepError>,
+}
+
+impl StepOutcome {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Receives the outcome of every step a worker runs, e.g. to export counters and latencies to an
+/// external metrics system. See `Worker::set_metrics`.
+pub trait Metrics: Send + Sync {
+    /// Called once per step run, after its hooks, with the step's `Stepable::metric_labels`.
+    fn record_step(&self, outcome: &StepOutcome, labels: &[(String, String)]);
+}
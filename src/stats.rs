@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A summary of every step a worker has run, as returned by `Worker::stats`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunStats {
+    /// The number of times a step was run.
+    pub runs: usize,
+    /// The number of runs that returned an error.
+    pub errors: usize,
+    /// Latency percentiles across all steps.
+    pub latency: Latency,
+    /// The same numbers broken down by step name.
+    pub steps: HashMap<String, StepStats>,
+}
+
+/// Counts and latencies for a single step.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StepStats {
+    pub runs: usize,
+    pub errors: usize,
+    pub latency: Latency,
+}
+
+/// Latency percentiles in milliseconds. Requests that received a response are sampled, and
+/// attempts that timed out are sampled at their timeout.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Latency {
+    pub p50: u64,
+    pub p95: u64,
+    pub p99: u64,
+}
+
+impl Latency {
+    fn from_samples(samples: &[u64]) -> Self {
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+
+        Latency {
+            p50: percentile(&sorted, 50.0),
+            p95: percentile(&sorted, 95.0),
+            p99: percentile(&sorted, 99.0),
+        }
+    }
+}
+
+/// The distribution of response body sizes in bytes, as returned by `Worker::body_size_stats`.
+/// Every body read in full is sampled, whether or not its step succeeded.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SizeStats {
+    pub max: u64,
+    pub mean: u64,
+    pub p95: u64,
+}
+
+impl SizeStats {
+    fn from_samples(samples: &[u64]) -> Self {
+        if samples.is_empty() {
+            return SizeStats::default();
+        }
+
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+
+        SizeStats {
+            max: sorted[sorted.len() - 1],
+            mean: sorted.iter().sum::<u64>() / sorted.len() as u64,
+            p95: percentile(&sorted, 95.0),
+        }
+    }
+}
+
+/// Derives each step's timeout from its own observed latency, as `p95 * factor` clamped to
+/// `min..=max`. Until a step has `min_samples` latency samples, its request's timeout is used.
+/// Attempts that timed out are sampled at their timeout. See `Worker::set_adaptive_timeout`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveTimeout {
+    pub factor: f64,
+    pub min: Duration,
+    pub max: Duration,
+    pub min_samples: usize,
+}
+
+impl AdaptiveTimeout {
+    /// Waits for 20 samples before adapting.
+    pub fn new(factor: f64, min: Duration, max: Duration) -> Self {
+        AdaptiveTimeout {
+            factor,
+            min,
+            max,
+            min_samples: 20,
+        }
+    }
+
+    pub fn with_min_samples(mut self, min_samples: usize) -> Self {
+        self.min_samples = min_samples;
+        self
+    }
+
+    /// Returns the timeout for latency samples in milliseconds, or `None` while there are too few.
+    pub fn timeout_for(&self, samples: &[u64]) -> Option<Duration> {
+        if samples.is_empty() || samples.len() < self.min_samples {
+            return None;
+        }
+
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        let p95 = Duration::from_millis(percentile(&sorted, 95.0));
+        Some(p95.mul_f64(self.factor).clamp(self.min, self.max))
+    }
+}
+
+/// Nearest-rank percentile of an already sorted slice. Returns 0 when there are no samples.
+pub(crate) fn percentile(sorted: &[u64], pct: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Collects the raw samples `RunStats` is computed from.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StatsRecorder {
+    steps: HashMap<String, StepSamples>,
+    body_sizes: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct StepSamples {
+    runs: usize,
+    errors: usize,
+    latencies: Vec<u64>,
+}
+
+impl StatsRecorder {
+    pub fn record_run(&mut self, step: &str, success: bool) {
+        let samples = self.steps.entry(step.to_string()).or_default();
+        samples.runs += 1;
+        if !success {
+            samples.errors += 1;
+        }
+    }
+
+    pub fn record_latency(&mut self, step: &str, ms: u64) {
+        self.steps
+            .entry(step.to_string())
+            .or_default()
+            .latencies
+            .push(ms);
+    }
+
+    /// The latency samples of `step` in milliseconds, in the order they were recorded.
+    pub fn latencies(&self, step: &str) -> &[u64] {
+        self.steps
+            .get(step)
+            .map_or(&[], |samples| samples.latencies.as_slice())
+    }
+
+    pub fn record_body_size(&mut self, bytes: usize) {
+        self.body_sizes.push(bytes as u64);
+    }
+
+    pub fn body_sizes(&self) -> SizeStats {
+        SizeStats::from_samples(&self.body_sizes)
+    }
+
+    /// Folds the samples recorded by another recorder into this one.
+    pub fn merge(&mut self, other: StatsRecorder) {
+        self.body_sizes.extend(other.body_sizes);
+        for (name, other) in other.steps {
+            let samples = self.steps.entry(name).or_default();
+            samples.runs += other.runs;
+            samples.errors += other.errors;
+            samples.latencies.extend(other.latencies);
+        }
+    }
+
+    pub fn summarize(&self) -> RunStats {
+        let mut all_latencies = Vec::new();
+        let mut stats = RunStats::default();
+
+        for (name, samples) in &self.steps {
+            stats.runs += samples.runs;
+            stats.errors += samples.errors;
+            all_latencies.extend_from_slice(&samples.latencies);
+
+            stats.steps.insert(
+                name.clone(),
+                StepStats {
+                    runs: samples.runs,
+                    errors: samples.errors,
+                    latency: Latency::from_samples(&samples.latencies),
+                },
+            );
+        }
+
+        stats.latency = Latency::from_samples(&all_latencies);
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentile_should_use_nearest_rank() {
+        let samples: Vec<u64> = (1..=100).collect();
+
+        assert_eq!(percentile(&samples, 50.0), 50);
+        assert_eq!(percentile(&samples, 95.0), 95);
+        assert_eq!(percentile(&samples, 99.0), 99);
+        assert_eq!(percentile(&[], 50.0), 0);
+        assert_eq!(percentile(&[7], 99.0), 7);
+    }
+
+    #[test]
+    fn adaptive_timeout_should_track_the_p95_latency() {
+        let adaptive =
+            AdaptiveTimeout::new(2.0, Duration::from_millis(100), Duration::from_secs(5))
+                .with_min_samples(10);
+        let mut recorder = StatsRecorder::default();
+
+        for _ in 0..9 {
+            recorder.record_latency("A", 200);
+        }
+        assert_eq!(adaptive.timeout_for(recorder.latencies("A")), None);
+        assert_eq!(adaptive.timeout_for(recorder.latencies("B")), None);
+
+        recorder.record_latency("A", 200);
+        assert_eq!(
+            adaptive.timeout_for(recorder.latencies("A")),
+            Some(Duration::from_millis(400))
+        );
+
+        // slower samples raise the p95 and the timeout with it
+        for ms in (1..=10).map(|i| i * 100) {
+            recorder.record_latency("A", ms);
+        }
+        assert_eq!(
+            adaptive.timeout_for(recorder.latencies("A")),
+            Some(Duration::from_millis(1_800))
+        );
+
+        let fast = vec![10; 20];
+        assert_eq!(
+            adaptive.timeout_for(&fast),
+            Some(Duration::from_millis(100)),
+            "clamped to min"
+        );
+        let slow = vec![10_000; 20];
+        assert_eq!(
+            adaptive.timeout_for(&slow),
+            Some(Duration::from_secs(5)),
+            "clamped to max"
+        );
+    }
+
+    #[test]
+    fn recorder_should_summarize_per_step_and_overall() {
+        let mut recorder = StatsRecorder::default();
+        for ms in [10, 20, 30] {
+            recorder.record_run("A", true);
+            recorder.record_latency("A", ms);
+        }
+        recorder.record_run("B", false);
+        recorder.record_latency("B", 100);
+
+        let stats = recorder.summarize();
+        assert_eq!(stats.runs, 4);
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.latency.p50, 20);
+        assert_eq!(stats.latency.p99, 100);
+        assert_eq!(stats.steps["A"].runs, 3);
+        assert_eq!(stats.steps["A"].latency.p95, 30);
+        assert_eq!(stats.steps["B"].errors, 1);
+    }
+
+    #[test]
+    fn recorder_should_compute_exact_percentiles() {
+        let mut recorder = StatsRecorder::default();
+        // recorded out of order, the percentiles sort them
+        for ms in (1..=100).rev() {
+            recorder.record_run("Fast", true);
+            recorder.record_latency("Fast", ms);
+        }
+        recorder.record_run("Slow", true);
+        recorder.record_latency("Slow", 1000);
+
+        let stats = recorder.summarize();
+        assert_eq!(
+            stats.steps["Fast"].latency,
+            Latency {
+                p50: 50,
+                p95: 95,
+                p99: 99
+            }
+        );
+        assert_eq!(
+            stats.latency,
+            Latency {
+                p50: 51,
+                p95: 96,
+                p99: 100
+            }
+        );
+        assert_eq!(stats.steps["Slow"].latency.p50, 1000);
+    }
+}
@@ -2,17 +2,83 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use bytes::Bytes;
+use reqwest::Proxy;
 
 use crate::context::Context;
 use crate::{Request, StepError};
 
+/// What a pipeline run does after a step succeeded, see `Stepable::on_success_action`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlowControl {
+    /// Runs the next step set on the context, if any.
+    Continue,
+    /// Ends the run, even if a next step was set.
+    Halt,
+    /// Runs the given step next.
+    GoTo(String),
+}
+
+/// How many bytes are read between two `Stepable::on_progress` calls.
+pub const PROGRESS_INTERVAL: u64 = 64 * 1024;
+
+/// Steps are shared between forked workers, so they must be `Send` and `Sync`.
 #[async_trait]
-pub trait Stepable {
+pub trait Stepable: Send + Sync {
+    /// A human readable label for the step.
     fn name(&self) -> String;
+    /// The unique key the step is registered and looked up by. Defaults to `name`.
+    fn key(&self) -> String {
+        self.name()
+    }
+    /// The keys of the steps this one follows, used by `Worker::describe`. Empty by default.
+    fn depends_on(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// Labels passed along with the step's outcome to the worker's `Metrics`, e.g. an endpoint
+    /// group or a region to slice dashboards by. Empty by default.
+    fn metric_labels(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
     fn on_request(&self) -> Request;
+    /// A proxy for every request of this step, used unless the request sets its own. None by default.
+    fn proxy(&self) -> Option<Proxy> {
+        None
+    }
+    /// Builds the request asynchronously, e.g. to fetch a fresh token first. The worker calls this
+    /// instead of `on_request`, which it delegates to unless implemented.
+    async fn on_request_async(&self, _ctx: &Context) -> Request {
+        self.on_request()
+    }
+    /// Adjusts the request built by `on_request` right before it is sent, with access to the context.
+    fn before_request(&self, _ctx: &Context, req: Request) -> Request {
+        req
+    }
+    /// Rewrites the response body before it is stored in the context and `on_success` runs,
+    /// e.g. to unwrap an envelope only this step's endpoint uses. Returns the body as is by default.
+    fn transform_body(&self, bytes: Bytes) -> Bytes {
+        bytes
+    }
     fn on_success(&self, ctx: &mut Context);
-    fn on_error(&self, ctx: &mut Context, err: StepError);
+    /// Decides where the run goes after `on_success`. Continues with the next step set on the
+    /// context by default.
+    fn on_success_action(&self, _ctx: &mut Context) -> FlowControl {
+        FlowControl::Continue
+    }
+    /// Passes the error to the worker's default error handler unless implemented.
+    fn on_error(&self, ctx: &mut Context, err: StepError) {
+        ctx.handle_default_error(&self.name(), &err);
+    }
     fn on_timeout(&self, ctx: &mut Context);
+    /// Called instead of `on_error` or `on_timeout` when a request with retries still fails after
+    /// its last attempt. `last` is the error of that attempt. Delegates to `on_error` by default.
+    fn on_retry_exhausted(&self, ctx: &mut Context, _attempts: u32, last: StepError) {
+        self.on_error(ctx, last)
+    }
+    /// Called as the response body downloads, with the bytes read so far and the length announced
+    /// by the server if any. Calls are throttled to one per `PROGRESS_INTERVAL` bytes, plus a
+    /// final one once the body is complete.
+    fn on_progress(&self, _downloaded: u64, _total: Option<u64>) {}
     // async fn execute(&self, res: StepperResponse) -> Result<StepperResponse, Error>;
 }
 
@@ -35,12 +101,11 @@ impl StepManager {
     }
 
     pub fn insert(&mut self, step: impl Stepable + 'static) {
-        self.handlers
-            .insert(step.name().parse().unwrap(), Arc::new(step));
+        self.handlers.insert(step.key(), Arc::new(step));
     }
 
     pub fn insert_arc(&mut self, step: Arc<dyn Stepable>) {
-        self.handlers.insert(step.name().parse().unwrap(), step);
+        self.handlers.insert(step.key(), step);
     }
     pub fn insert_many(&mut self, steps: Vec<Arc<dyn Stepable>>) {
         for step in steps {
@@ -49,20 +114,35 @@ impl StepManager {
     }
 
     pub fn get(&self, step: &str) -> Option<&Arc<dyn Stepable>> {
-        let step = self.handlers.get(step).unwrap();
-        Some(step)
+        self.handlers.get(step)
+    }
+
+    /// Returns the keys of every registered step, sorted.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.handlers.keys().cloned().collect();
+        keys.sort();
+        keys
     }
 
-    pub fn len(&mut self) -> usize {
+    pub fn len(&self) -> usize {
         self.handlers.len()
     }
 
-    pub fn contains_name(&mut self, step: &String) -> bool {
-        self.handlers.contains_key(step)
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
     }
 
-    pub fn contains_step(&mut self, step: impl Stepable) -> bool {
-        self.handlers.contains_key(step.name().as_str())
+    /// Whether a step is registered under the key `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.handlers.contains_key(name)
+    }
+
+    pub fn contains_name(&self, step: &str) -> bool {
+        self.contains(step)
+    }
+
+    pub fn contains_step(&self, step: impl Stepable) -> bool {
+        self.contains(&step.key())
     }
 }
 
@@ -121,4 +201,54 @@ mod tests {
         assert_eq!(req.method(), Method::GET);
         assert_eq!(req.status_codes(), Some(vec![200]));
     }
+
+    struct Localized {
+        key: &'static str,
+    }
+
+    impl Stepable for Localized {
+        fn name(&self) -> String {
+            "Fetch robots.txt".to_string()
+        }
+
+        fn key(&self) -> String {
+            self.key.to_string()
+        }
+
+        fn on_request(&self) -> Request {
+            Request::new(Method::GET, "https://test.com".to_string())
+        }
+
+        fn on_success(&self, _ctx: &mut Context) {}
+
+        fn on_timeout(&self, _ctx: &mut Context) {}
+    }
+
+    #[test]
+    fn step_manager_should_register_steps_by_key() {
+        let mut steps = StepManager::new();
+        steps.insert(Localized { key: "robots_en" });
+        steps.insert(Localized { key: "robots_fr" });
+        steps.insert(RobotsTxt);
+
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps.get("robots_fr").unwrap().key(), "robots_fr");
+        assert!(steps.get("Fetch robots.txt").is_none());
+        assert!(steps.contains_step(RobotsTxt));
+    }
+
+    #[test]
+    fn step_manager_should_check_for_steps_without_mutability() {
+        let empty = StepManager::new();
+        assert!(empty.is_empty());
+        assert!(!empty.contains("robots_en"));
+
+        let mut steps = StepManager::new();
+        steps.insert(Localized { key: "robots_en" });
+        let steps = steps;
+
+        assert!(steps.contains("robots_en"));
+        assert!(!steps.contains("robots_fr"));
+        assert_eq!(steps.len(), 1);
+    }
 }
@@ -0,0 +1,201 @@
+//! Incremental parsing of response bodies read chunk by chunk, see `Worker::stream_ndjson` and
+//! `Worker::stream_sse`.
+
+/// Splits bytes arriving in arbitrary chunks into lines, keeping a partial line until the chunk
+/// completing it arrives. Lines end with `\n`, `\r` or `\r\n`, as in `text/event-stream`.
+#[derive(Debug, Default)]
+pub(crate) struct LineBuffer {
+    pending: Vec<u8>,
+    // the last byte seen was a `\r`, so a `\n` right after it belongs to the same line ending
+    after_cr: bool,
+}
+
+impl LineBuffer {
+    /// Appends a chunk and returns the lines it completed, without their line endings.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        let mut lines = Vec::new();
+        for &byte in chunk {
+            let after_cr = std::mem::replace(&mut self.after_cr, byte == b'\r');
+            match byte {
+                b'\n' if after_cr => {}
+                b'\n' | b'\r' => lines.push(std::mem::take(&mut self.pending)),
+                _ => self.pending.push(byte),
+            }
+        }
+        lines
+    }
+
+    /// Returns the last line once the body ended, if it wasn't terminated by a line ending.
+    #[cfg(any(test, feature = "json"))]
+    pub fn finish(&mut self) -> Option<Vec<u8>> {
+        self.after_cr = false;
+        let line = std::mem::take(&mut self.pending);
+        (!line.is_empty()).then_some(line)
+    }
+}
+
+/// An event of a `text/event-stream` response. See `Worker::stream_sse`.
+#[cfg(feature = "sse")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    /// The `event` field, `message` when the event didn't name its type.
+    pub event: String,
+    /// The `data` fields of the event, joined by newlines.
+    pub data: String,
+    /// The last `id` the stream sent, which carries over to the events that don't set one.
+    pub id: Option<String>,
+    /// The reconnection time in milliseconds, if the event set one.
+    pub retry: Option<u64>,
+}
+
+/// Builds events from the lines of a `text/event-stream` body, following the parsing rules of
+/// the HTML spec: comments are skipped and events without data aren't dispatched.
+#[cfg(feature = "sse")]
+#[derive(Debug, Default)]
+pub(crate) struct SseParser {
+    event: Option<String>,
+    data: Option<String>,
+    last_id: Option<String>,
+    retry: Option<u64>,
+}
+
+#[cfg(feature = "sse")]
+impl SseParser {
+    /// Handles one line, returning the event a blank line completed.
+    pub fn line(&mut self, line: &[u8]) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+
+        let line = String::from_utf8_lossy(line);
+        let line = line.strip_prefix('\u{feff}').unwrap_or(&line);
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => {
+                let data = self.data.get_or_insert_with(String::new);
+                data.push_str(value);
+                data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.last_id = Some(value.to_string()),
+            "retry" => self.retry = value.parse().ok().or(self.retry),
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        let retry = self.retry.take();
+        let mut data = self.data.take()?;
+        data.pop();
+
+        Some(SseEvent {
+            event: event.unwrap_or_else(|| "message".to_string()),
+            data,
+            id: self.last_id.clone(),
+            retry,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_buffer_should_join_lines_split_across_chunks() {
+        let mut buffer = LineBuffer::default();
+
+        assert!(buffer.push(b"fir").is_empty());
+        assert_eq!(buffer.push(b"st\r\nsec"), vec![b"first".to_vec()]);
+        assert_eq!(
+            buffer.push(b"ond\n\nthird\r"),
+            vec![b"second".to_vec(), Vec::new(), b"third".to_vec()]
+        );
+        assert!(
+            buffer.push(b"\n").is_empty(),
+            "a CRLF split across chunks ends a single line"
+        );
+        assert_eq!(buffer.finish(), None);
+
+        buffer.push(b"unterminated");
+        assert_eq!(buffer.finish(), Some(b"unterminated".to_vec()));
+    }
+
+    #[test]
+    fn line_buffer_should_split_on_cr_lf_and_crlf() {
+        let mut buffer = LineBuffer::default();
+
+        assert_eq!(
+            buffer.push(b"crlf\r\nlf\ncr\rlast"),
+            vec![b"crlf".to_vec(), b"lf".to_vec(), b"cr".to_vec()]
+        );
+        assert_eq!(
+            buffer.push(b"\r\n\r\n\r\r\n\n"),
+            vec![
+                b"last".to_vec(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new()
+            ]
+        );
+        assert_eq!(buffer.finish(), None);
+    }
+
+    #[cfg(feature = "sse")]
+    #[test]
+    fn sse_parser_should_build_events_from_lines() {
+        let mut parser = SseParser::default();
+        let mut events = Vec::new();
+        let body = ": keep-alive\n\
+                    data: first\n\
+                    \n\
+                    event: update\n\
+                    id: 7\n\
+                    retry: 1500\n\
+                    data: line one\n\
+                    data:line two\n\
+                    data\n\
+                    \n\
+                    event: ignored\n\
+                    \n\
+                    data: {\"n\": 3}\n\
+                    \n";
+        for line in body.lines() {
+            events.extend(parser.line(line.as_bytes()));
+        }
+
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: "message".to_string(),
+                    data: "first".to_string(),
+                    id: None,
+                    retry: None,
+                },
+                SseEvent {
+                    event: "update".to_string(),
+                    data: "line one\nline two\n".to_string(),
+                    id: Some("7".to_string()),
+                    retry: Some(1500),
+                },
+                SseEvent {
+                    event: "message".to_string(),
+                    data: "{\"n\": 3}".to_string(),
+                    id: Some("7".to_string()),
+                    retry: None,
+                },
+            ]
+        );
+    }
+}
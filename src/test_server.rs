@@ -0,0 +1,313 @@
+#![allow(dead_code)]
+
+//! A tiny HTTP/1.1 server used by the unit tests so steps can be exercised against a real socket
+//! without reaching out to the internet.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+type Handler = dyn Fn(&RecordedRequest) -> TestResponse + Send + Sync;
+
+/// A request as it was received by the test server.
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedRequest {
+    pub method: String,
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    /// Returns the first header matching `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// A canned response returned by the test server.
+#[derive(Debug, Clone)]
+pub(crate) struct TestResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    delay: Option<Duration>,
+    chunks: Option<Vec<Vec<u8>>>,
+    raw: Option<Vec<u8>>,
+}
+
+impl TestResponse {
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: vec![],
+            delay: None,
+            chunks: None,
+            raw: None,
+        }
+    }
+
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self::status(200).body(body)
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Waits before writing the response, keeping the request "in flight".
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Sends the body with chunked transfer encoding, flushing after every chunk.
+    pub fn chunked(mut self, chunks: Vec<Vec<u8>>) -> Self {
+        self.chunks = Some(chunks);
+        self
+    }
+
+    /// Writes the given bytes verbatim and closes the connection.
+    pub fn raw(bytes: impl Into<Vec<u8>>) -> Self {
+        let mut res = Self::status(200);
+        res.raw = Some(bytes.into());
+        res
+    }
+}
+
+pub(crate) struct TestServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    connections: Arc<AtomicUsize>,
+    max_in_flight: Arc<AtomicUsize>,
+}
+
+impl TestServer {
+    /// Starts a server on a random local port, answering every request with `handler`.
+    pub async fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&RecordedRequest) -> TestResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handler: Arc<Handler> = Arc::new(handler);
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+        let server = Self {
+            addr,
+            requests: requests.clone(),
+            connections: connections.clone(),
+            max_in_flight: max_in_flight.clone(),
+        };
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.fetch_add(1, Ordering::SeqCst);
+                let conn = Connection {
+                    handler: handler.clone(),
+                    requests: requests.clone(),
+                    in_flight: in_flight.clone(),
+                    max_in_flight: max_in_flight.clone(),
+                };
+                tokio::spawn(conn.serve(stream));
+            }
+        });
+
+        server
+    }
+
+    /// Starts a server that answers every request with a 200 and the given body.
+    pub async fn ok(body: &'static str) -> Self {
+        Self::start(move |_| TestResponse::ok(body)).await
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn hits(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    /// The number of TCP connections accepted so far.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// The highest number of requests that were being handled at the same time.
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight.load(Ordering::SeqCst)
+    }
+}
+
+struct Connection {
+    handler: Arc<Handler>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    in_flight: Arc<AtomicUsize>,
+    max_in_flight: Arc<AtomicUsize>,
+}
+
+impl Connection {
+    async fn serve(self, mut stream: TcpStream) {
+        let mut buf = Vec::new();
+        while let Some(req) = read_request(&mut stream, &mut buf).await {
+            let current = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(current, Ordering::SeqCst);
+            self.requests.lock().unwrap().push(req.clone());
+
+            let res = (self.handler)(&req);
+            if let Some(delay) = res.delay {
+                tokio::time::sleep(delay).await;
+            }
+            let keep_alive = write_response(&mut stream, &res, req.method == "HEAD")
+                .await
+                .is_ok();
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            if !keep_alive || res.raw.is_some() {
+                break;
+            }
+        }
+        let _ = stream.shutdown().await;
+    }
+}
+
+async fn read_request(stream: &mut TcpStream, buf: &mut Vec<u8>) -> Option<RecordedRequest> {
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        let mut chunk = [0u8; 4096];
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+    buf.drain(..head_end + 4);
+
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?.to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect();
+
+    let content_length = headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, v)| v.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    while buf.len() < content_length {
+        let mut chunk = [0u8; 4096];
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let body = buf.drain(..content_length).collect();
+
+    Some(RecordedRequest {
+        method,
+        target,
+        headers,
+        body,
+    })
+}
+
+// answers to `HEAD` requests announce the body's length without sending it
+async fn write_response(
+    stream: &mut TcpStream,
+    res: &TestResponse,
+    head_only: bool,
+) -> std::io::Result<()> {
+    if let Some(raw) = &res.raw {
+        stream.write_all(raw).await?;
+        return stream.flush().await;
+    }
+
+    let mut head = format!("HTTP/1.1 {} {}\r\n", res.status, reason(res.status));
+    for (k, v) in &res.headers {
+        head.push_str(&format!("{}: {}\r\n", k, v));
+    }
+
+    match &res.chunks {
+        Some(chunks) => {
+            head.push_str("Transfer-Encoding: chunked\r\n\r\n");
+            stream.write_all(head.as_bytes()).await?;
+            stream.flush().await?;
+            for chunk in chunks {
+                stream
+                    .write_all(format!("{:x}\r\n", chunk.len()).as_bytes())
+                    .await?;
+                stream.write_all(chunk).await?;
+                stream.write_all(b"\r\n").await?;
+                stream.flush().await?;
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            stream.write_all(b"0\r\n\r\n").await?;
+        }
+        None => {
+            let has_length = res
+                .headers
+                .iter()
+                .any(|(k, _)| k.eq_ignore_ascii_case("content-length"));
+            if !has_length {
+                head.push_str(&format!("Content-Length: {}\r\n", res.body.len()));
+            }
+            head.push_str("\r\n");
+            stream.write_all(head.as_bytes()).await?;
+            if !head_only {
+                stream.write_all(&res.body).await?;
+            }
+        }
+    }
+
+    stream.flush().await
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        304 => "Not Modified",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,127 @@
+//! Sends requests over a Unix domain socket, for talking to local daemons such as the Docker API.
+//!
+//! reqwest has no Unix socket connector, so the built request is handed to a hyper client using
+//! `hyperlocal`'s connector and the response is converted back into a `reqwest::Response`.
+//! The url's host is only a placeholder: every request goes to the configured socket.
+//! Cookies, proxies and response decompression are not applied on this path, and streaming
+//! bodies (such as multipart forms) are not supported.
+
+use std::io;
+use std::path::Path;
+
+use hyper::Client;
+use hyperlocal::{UnixClientExt, Uri};
+use reqwest::header::HOST;
+use reqwest::{RequestBuilder, Response};
+
+pub(crate) async fn send(builder: RequestBuilder, socket: &Path) -> io::Result<Response> {
+    let request = builder.build().map_err(io::Error::other)?;
+
+    let url = request.url();
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+
+    let body = match request.body() {
+        Some(body) => match body.as_bytes() {
+            Some(bytes) => hyper::Body::from(bytes.to_vec()),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "streaming bodies can't be sent over a unix socket",
+                ))
+            }
+        },
+        None => hyper::Body::empty(),
+    };
+
+    let mut hyper_request = hyper::Request::builder()
+        .method(request.method().clone())
+        .uri(Uri::new(socket, &path))
+        .body(body)
+        .map_err(io::Error::other)?;
+    *hyper_request.headers_mut() = request.headers().clone();
+    if let Some(host) = url.host_str() {
+        if let Ok(host) = host.parse() {
+            hyper_request.headers_mut().insert(HOST, host);
+        }
+    }
+
+    let client = Client::unix();
+    let response = match request.timeout() {
+        Some(timeout) => tokio::time::timeout(*timeout, client.request(hyper_request))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Request timed out"))?,
+        None => client.request(hyper_request).await,
+    }
+    .map_err(io::Error::other)?;
+
+    let (parts, body) = response.into_parts();
+    let response = hyper::Response::from_parts(parts, reqwest::Body::wrap_stream(body));
+    Ok(Response::from(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use reqwest::Method;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixListener;
+
+    use crate::{Context, Request, StepError, Stepable, Worker};
+
+    struct DockerInfo;
+
+    impl Stepable for DockerInfo {
+        fn name(&self) -> String {
+            String::from("DockerInfo")
+        }
+
+        fn on_request(&self) -> Request {
+            Request::new(Method::GET, "http://localhost/v1.43/info?all=1".to_string())
+        }
+
+        fn on_success(&self, _ctx: &mut Context) {}
+
+        fn on_error(&self, _ctx: &mut Context, _err: StepError) {}
+
+        fn on_timeout(&self, _ctx: &mut Context) {}
+    }
+
+    #[tokio::test]
+    async fn it_should_send_requests_over_a_unix_socket() {
+        let socket = std::env::temp_dir().join(format!("mimicr-uds-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        let received = Arc::new(Mutex::new(String::new()));
+        let server_received = received.clone();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 4096];
+            let n = stream.read(&mut buf).await.unwrap();
+            *server_received.lock().unwrap() = String::from_utf8_lossy(&buf[..n]).to_string();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 16\r\n\r\n{\"Containers\":3}")
+                .await
+                .unwrap();
+        });
+
+        let mut worker = Worker::new();
+        worker
+            .context_mut()
+            .client_settings_mut()
+            .set_unix_socket(Some(socket.clone()));
+        worker.add_step(DockerInfo);
+
+        worker.try_step("DockerInfo").await.unwrap();
+        std::fs::remove_file(&socket).unwrap();
+
+        assert_eq!(worker.context().body_text().unwrap(), "{\"Containers\":3}");
+        let received = received.lock().unwrap();
+        assert!(received.starts_with("GET /v1.43/info?all=1 HTTP/1.1\r\n"));
+        assert!(received.to_lowercase().contains("host: localhost"));
+    }
+}
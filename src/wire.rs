@@ -0,0 +1,108 @@
+//! Requests and responses as they roughly look on the wire, see `Worker::set_wire_logger`.
+
+use std::sync::Arc;
+
+use reqwest::header::{HeaderMap, HOST};
+use reqwest::{StatusCode, Url, Version};
+
+use crate::SentRequest;
+
+/// Bytes exchanged for a step's request, reconstructed as HTTP/1.1 text from the request built
+/// and the response read, so they are close to but not exactly what went over the connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireEvent {
+    /// The request line, headers and body of a request about to be sent.
+    RequestSent(Vec<u8>),
+    /// The status line, headers and body of a response as received, before decompression.
+    ResponseReceived(Vec<u8>),
+}
+
+/// Receives the wire events of every step's request. See `Worker::set_wire_logger`.
+pub type WireLogger = Arc<dyn Fn(WireEvent) + Send + Sync>;
+
+pub(crate) fn request_bytes(sent: &SentRequest, body: Option<&[u8]>) -> Vec<u8> {
+    let url = Url::parse(&sent.url).ok();
+    let target = match &url {
+        Some(url) => match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        },
+        None => sent.url.clone(),
+    };
+
+    let mut out = format!("{} {} HTTP/1.1\r\n", sent.method, target).into_bytes();
+    let host = url.as_ref().and_then(|url| {
+        let host = url.host_str()?;
+        Some(match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        })
+    });
+    if let Some(host) = host.filter(|_| !sent.headers.contains_key(HOST)) {
+        out.extend_from_slice(format!("host: {}\r\n", host).as_bytes());
+    }
+    write_headers(&mut out, &sent.headers);
+    out.extend_from_slice(body.unwrap_or_default());
+    out
+}
+
+pub(crate) fn response_bytes(
+    version: Version,
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Vec<u8> {
+    let mut out = format!("{:?} {}\r\n", version, status).into_bytes();
+    write_headers(&mut out, headers);
+    out.extend_from_slice(body);
+    out
+}
+
+fn write_headers(out: &mut Vec<u8>, headers: &HeaderMap) {
+    for (name, value) in headers {
+        out.extend_from_slice(name.as_str().as_bytes());
+        out.extend_from_slice(b": ");
+        out.extend_from_slice(value.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+    use reqwest::Method;
+
+    use super::*;
+
+    #[test]
+    fn it_should_write_a_request_with_its_host_and_body() {
+        let mut headers = HeaderMap::new();
+        headers.insert("accept", HeaderValue::from_static("*/*"));
+        let sent = SentRequest {
+            method: Method::POST,
+            url: "http://example.com:8080/items?page=2".to_string(),
+            headers,
+        };
+
+        let bytes = request_bytes(&sent, Some(b"hello"));
+
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "POST /items?page=2 HTTP/1.1\r\nhost: example.com:8080\r\naccept: */*\r\n\r\nhello"
+        );
+    }
+
+    #[test]
+    fn it_should_write_a_response_with_its_status_line() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-length", HeaderValue::from_static("2"));
+
+        let bytes = response_bytes(Version::HTTP_11, StatusCode::OK, &headers, b"ok");
+
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok"
+        );
+    }
+}
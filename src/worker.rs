@@ -1,14 +1,96 @@
 #![allow(dead_code)]
 
+use crate::cache::{CacheKey, ResponseCache};
 use crate::context::Context;
-use crate::steps::StepManager;
-use crate::{StepError, Stepable};
-use std::io::Error;
+use crate::cookies::CookieJar;
+use crate::http_requester::{new_cookie_store, HeaderTransform};
+use crate::report::{Metrics, RunReport, StepOutcome};
+use crate::stats::{AdaptiveTimeout, StatsRecorder};
+use crate::steps::{FlowControl, StepManager, PROGRESS_INTERVAL};
+#[cfg(any(feature = "json", feature = "sse"))]
+use crate::stream::LineBuffer;
+#[cfg(feature = "wire-log")]
+use crate::wire::{self, WireEvent, WireLogger};
+#[cfg(feature = "json")]
+use crate::JsonKind;
+use crate::{
+    ContentLengthCheck, ErrorHandler, Request, RunStats, SizeStats, StepError, Stepable,
+    TimeoutPhase, UrlNormalizer,
+};
+use bytes::Bytes;
+use reqwest::header::{HeaderMap, CONTENT_ENCODING, CONTENT_TYPE, ETAG, RETRY_AFTER};
+use reqwest::{Method, Response, StatusCode};
+#[cfg(feature = "json")]
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{AcquireError, Mutex, OwnedSemaphorePermit};
 
 pub struct Worker {
-    steps: StepManager,
-    pub ctx: Context,
+    /// Shared with forks, and copied on write when a step is added.
+    steps: Arc<StepManager>,
+    ctx: Context,
+    /// Counts and latency samples of every step run by this worker.
+    stats: StatsRecorder,
+    /// Whether `on_success` is skipped when a step's body hasn't changed since its last run.
+    skip_unchanged: bool,
+    /// The body hash of the last successful run of each step.
+    last_hashes: HashMap<String, u64>,
+    /// Whether steps whose request was already answered successfully are skipped.
+    skip_seen: bool,
+    /// The fingerprints of the requests answered successfully so far.
+    seen_requests: HashSet<(usize, u64)>,
+    /// Fresh `GET` responses, answered without a request while caching is enabled.
+    cache: Option<ResponseCache>,
+    /// Runs before the first step of `run`.
+    warmup: Option<RunHook>,
+    /// Runs after the last step of `run`.
+    teardown: Option<RunHook>,
+    /// The cookie jars selected by `with_session`, by session name.
+    sessions: HashMap<String, Arc<CookieJar>>,
+    /// Overrides the timeout of requests based on the latency of their step.
+    adaptive_timeout: Option<AdaptiveTimeout>,
+    /// Whether requests wait for the rate limit to reset once a response said none are left.
+    pause_on_rate_limit: bool,
+    /// When the rate limit reported by the last response resets.
+    rate_limited_until: Option<Instant>,
+    /// Receives the outcome of every step run.
+    metrics: Option<Arc<dyn Metrics>>,
+    /// Receives the bytes of every request sent and response read by a step.
+    #[cfg(feature = "wire-log")]
+    wire_logger: Option<WireLogger>,
+}
+
+/// Prepares or cleans up the context around a pipeline run. See `Worker::set_warmup`.
+pub type RunHook = Arc<dyn Fn(&mut Context) -> Result<(), StepError> + Send + Sync>;
+
+// why an attempt at a step failed
+enum Failure {
+    /// The request timed out, reported to `on_timeout`.
+    Timeout(Box<dyn std::error::Error>),
+    /// The request failed, reported to `on_error` along with the error returned to the caller.
+    Error(StepError, Box<dyn std::error::Error>),
+    /// The request couldn't be attempted, which is neither retried nor reported to the hooks.
+    Aborted(Box<dyn std::error::Error>),
+    /// The response has a status the request retries on, with the delay its `Retry-After` asks.
+    /// Reported to `on_error` like any other error once the retries are used up.
+    RetryStatus(StepError, Option<Duration>),
+}
+
+impl Failure {
+    fn step(error: StepError) -> Self {
+        Failure::Error(error.clone(), Box::new(error))
+    }
+}
+
+// the step hook a failure is reported to
+enum Hook {
+    Error,
+    Timeout,
+    /// The retries ran out after this many attempts.
+    RetryExhausted(u32),
 }
 
 impl Default for Worker {
@@ -19,313 +101,4139 @@ impl Default for Worker {
 
 impl Worker {
     pub fn new() -> Self {
-        let steps = StepManager::new();
+        let steps = Arc::new(StepManager::new());
         let ctx = Context::new();
-        Worker { steps, ctx }
+        Worker {
+            steps,
+            ctx,
+            stats: StatsRecorder::default(),
+            skip_unchanged: false,
+            last_hashes: HashMap::new(),
+            skip_seen: false,
+            seen_requests: HashSet::new(),
+            cache: None,
+            warmup: None,
+            teardown: None,
+            sessions: HashMap::new(),
+            adaptive_timeout: None,
+            pause_on_rate_limit: false,
+            rate_limited_until: None,
+            metrics: None,
+            #[cfg(feature = "wire-log")]
+            wire_logger: None,
+        }
     }
 
-    pub fn add_step(&mut self, step: impl Stepable + 'static) {
-        self.steps.insert(step);
+    /// Limits the number of simultaneous in-flight requests to `limit`.
+    /// A permit is acquired before each request is sent and held until its body has been read.
+    pub fn with_concurrency_limit(mut self, limit: usize) -> Self {
+        self.ctx.limits_mut().set_overall(limit);
+        self
     }
 
-    pub fn add_many_steps(&mut self, steps: Vec<Arc<dyn Stepable>>) {
-        self.steps.insert_many(steps);
+    /// Limits the number of simultaneous in-flight requests to any single host to `limit`, on
+    /// top of the overall concurrency limit. Forks and queue lanes share the limit with this
+    /// worker. Like the overall limit, a permit is held until the body has been read.
+    pub fn set_per_host_concurrency(&mut self, limit: usize) {
+        self.ctx.limits_mut().set_per_host(limit);
     }
 
-    pub fn add_step_arc(&mut self, step: Arc<dyn Stepable>) {
-        self.steps.insert_arc(step);
+    /// Sets a handler for the errors of steps that don't implement `Stepable::on_error`,
+    /// e.g. to log them all in one place.
+    pub fn set_default_error_handler(&mut self, handler: ErrorHandler) {
+        self.ctx.set_default_error_handler(Some(handler));
     }
 
-    pub fn steps(self) -> StepManager {
-        self.steps
+    /// Makes the default error handler see every step error, timeouts included, after the step's
+    /// own `on_error`, `on_timeout` or `on_retry_exhausted`.
+    pub fn always_use_default_error_handler(&mut self, always: bool) {
+        self.ctx.set_always_default_error_handler(always);
     }
 
-    // get the step by name
-    fn get_step(&self, name: &str) -> Option<Arc<dyn Stepable>> {
-        match self.steps.get(name) {
-            Some(step) => Some(step.clone()),
-            None => None,
-        }
+    /// Runs `transform` on the headers of every step's request, e.g. to add a timestamp header.
+    /// Lighter than a full interceptor when only headers need to change.
+    pub fn set_header_transform(&mut self, transform: HeaderTransform) {
+        self.ctx
+            .http_requester_mut()
+            .set_header_transform(Some(transform));
     }
 
-    // start the instant timer to run the step
-    // run send() on the request_builder
-    // stop the instant timer
-    pub async fn try_step(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let step = self.get_step(name).unwrap();
-        let req = step.on_request();
-
-        if req.get_skip_to_step().is_some() {
-            self.ctx
-                .set_next_step(req.get_skip_to_step().unwrap().clone());
-            return Ok(());
-        }
+    /// Sends every step's request to the url `normalizer` rewrites its url to, which is also
+    /// the url responses are cached under, e.g. `DefaultUrlNormalizer` to canonicalize them.
+    pub fn set_url_normalizer(&mut self, normalizer: impl UrlNormalizer + 'static) {
+        self.ctx
+            .http_requester_mut()
+            .set_url_normalizer(Some(Arc::new(normalizer)));
+    }
 
-        self.ctx.update_from_request(req)?;
-        self.ctx.set_current_step(name.to_string());
+    /// Answers every request from `mock` instead of sending it, to test steps offline.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn set_mock_transport(&mut self, mock: std::sync::Arc<crate::MockTransport>) {
+        self.ctx.http_requester_mut().set_mock_transport(Some(mock));
+    }
 
-        let req_builder = self.ctx.get_request_builder().unwrap();
+    /// Skips `on_success` when a step's response body is identical to the one of its last
+    /// successful run, so unchanged content isn't processed twice. The step still succeeds.
+    pub fn skip_unchanged_responses(&mut self, skip: bool) {
+        self.skip_unchanged = skip;
+    }
 
-        // Start processing the request and time it.
-        let stop_watch = std::time::Instant::now();
-        let res = match req_builder.send().await {
-            Ok(res) => res,
-            Err(err) => {
-                if err.is_timeout() {
-                    step.on_timeout(&mut self.ctx);
-                    return Err(Self::timeout_error());
-                }
+    /// Skips steps whose request has the same `Request::fingerprint` as one answered successfully
+    /// before in the same session, so identical requests aren't fetched twice. A skipped step
+    /// succeeds without sending anything or running its hooks. `reset` forgets the requests seen.
+    pub fn skip_seen_requests(&mut self, skip: bool) {
+        self.skip_seen = skip;
+    }
 
-                step.on_error(&mut self.ctx, StepError::ReqwestError(err.to_string()));
-                return Err(Box::new(err));
-            }
-        };
-        self.ctx
-            .set_time_elapsed(stop_watch.elapsed().as_millis() as u64);
+    /// Keeps successful `GET` responses in memory for as long as their `Cache-Control: max-age`
+    /// or `Expires` header allows, and answers steps requesting the same url from memory until
+    /// then. Responses marked `no-store`, `private` or `no-cache` are never cached.
+    /// Cached responses are only served to requests from the same session with the same
+    /// `Authorization` and `Cookie` headers, and the same values of the headers their `Vary` names.
+    pub fn cache_responses(&mut self, enabled: bool) {
+        self.cache = enabled.then(ResponseCache::default);
+    }
 
-        if !self.check_status_code(res.status().as_u16()) {
-            let error = StepError::StatusCodeNotFound(
-                res.status().as_u16() as i32,
-                self.ctx.get_status_codes().unwrap_or_default(),
-            );
+    /// Sets the timeout of each step's requests from the latency the step has shown so far,
+    /// once it has enough samples. `None` goes back to the timeouts of the requests.
+    /// An attempt that timed out is sampled at its timeout, so a timeout that became too short
+    /// grows back by `factor` with each attempt cut off.
+    pub fn set_adaptive_timeout(&mut self, adaptive: Option<AdaptiveTimeout>) {
+        self.adaptive_timeout = adaptive;
+    }
 
-            step.on_error(&mut self.ctx, error.clone());
-            return Err(Box::new(error));
+    /// Holds back the next request once a response reports `X-RateLimit-Remaining: 0`, until
+    /// its `X-RateLimit-Reset`, read as seconds or as a unix time. The pause is capped like a
+    /// `Retry-After`, see `ClientSettings::set_max_retry_after`. Off by default.
+    pub fn pause_on_rate_limit(&mut self, pause: bool) {
+        self.pause_on_rate_limit = pause;
+        if !pause {
+            self.rate_limited_until = None;
         }
+    }
 
-        let body = match res.bytes().await {
-            Ok(body) => body,
-            Err(err) => {
-                step.on_error(&mut self.ctx, StepError::ReqwestError(err.to_string()));
-                return Err(Box::new(err));
-            }
-        };
+    /// Hands `logger` the request line, headers and body of every step's request before it is
+    /// sent, and the status line, headers and undecoded body of its response once read, e.g. to
+    /// debug encoding issues. Cached responses and streamed bodies aren't logged.
+    #[cfg(feature = "wire-log")]
+    pub fn set_wire_logger(&mut self, logger: WireLogger) {
+        self.wire_logger = Some(logger);
+    }
 
-        self.ctx.set_response_body(body);
+    /// Hands the outcome of every step run, along with the step's `Stepable::metric_labels`, to
+    /// `metrics`. Forks and queue lanes report to it as well.
+    pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) {
+        self.metrics = Some(metrics);
+    }
 
-        // clear the next step since the context is being reused, this fixes the infinite loop bug
-        self.ctx.clear_next_step();
-        step.on_success(&mut self.ctx);
+    /// Sets a hook `run` calls once before its first step, e.g. to fetch a token into the context.
+    /// When it fails, no step runs and the report holds a single failed `warmup` outcome.
+    pub fn set_warmup(&mut self, warmup: RunHook) {
+        self.warmup = Some(warmup);
+    }
 
-        Ok(())
+    /// Sets a hook `run` calls once after its last step, whether or not the steps succeeded.
+    /// When it fails, a failed `teardown` outcome is added to the report.
+    pub fn set_teardown(&mut self, teardown: RunHook) {
+        self.teardown = Some(teardown);
     }
 
-    fn timeout_error() -> Box<Error> {
-        Box::new(std::io::Error::new(
-            std::io::ErrorKind::TimedOut,
-            "Request timed out",
-        ))
+    /// Makes the steps run from now on use the cookie jar of session `name`, creating an empty
+    /// one the first time, so several logical sessions don't share cookies within one worker.
+    /// The jar in use before the first session was selected is kept as the `default` session.
+    pub fn with_session(&mut self, name: &str) -> &mut Self {
+        let jar = self.session_jar(name);
+        self.ctx.http_requester_mut().set_cookie_store(jar);
+        self
     }
 
-    fn check_status_code(&self, status_code: u16) -> bool {
-        match &self.ctx.get_status_codes() {
-            Some(codes) => {
-                if codes.is_empty() {
-                    return (200..300).contains(&status_code);
-                }
-                codes.contains(&status_code)
-            }
-            None => (200..300).contains(&status_code),
+    // the cookie jar of a session, created empty the first time
+    fn session_jar(&mut self, name: &str) -> Arc<CookieJar> {
+        if self.sessions.is_empty() {
+            let current = self.ctx.http_requester().cookie_store();
+            self.sessions.insert("default".to_string(), current);
         }
+
+        let jar = self
+            .sessions
+            .entry(name.to_string())
+            .or_insert_with(new_cookie_store);
+        Arc::clone(jar)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::worker::Worker;
-    use crate::{Context, Request, StepError, Stepable};
-    use async_trait::async_trait;
-    use reqwest::Method;
-    use std::sync::Arc;
+    pub fn add_step(&mut self, step: impl Stepable + 'static) {
+        Arc::make_mut(&mut self.steps).insert(step);
+    }
 
-    #[derive(Clone, Copy)]
-    struct RobotsTxt;
+    pub fn add_many_steps(&mut self, steps: Vec<Arc<dyn Stepable>>) {
+        Arc::make_mut(&mut self.steps).insert_many(steps);
+    }
 
-    const ROBOTS_TXT: &str = "RobotsTxt";
-    const SKIPPABLE_STEP: &str = "SkippableStep";
+    pub fn add_step_arc(&mut self, step: Arc<dyn Stepable>) {
+        Arc::make_mut(&mut self.steps).insert_arc(step);
+    }
 
-    #[async_trait]
-    impl Stepable for RobotsTxt {
-        fn name(&self) -> String {
-            String::from(ROBOTS_TXT)
-        }
+    /// Whether a step is registered under the key `name`, e.g. to check a step named in a
+    /// config file before running it.
+    pub fn has_step(&self, name: &str) -> bool {
+        self.steps.contains(name)
+    }
 
-        fn on_request(&self) -> Request {
-            Request::new(Method::GET, "https://google.com".to_string())
-        }
+    pub fn steps(self) -> StepManager {
+        Arc::try_unwrap(self.steps).unwrap_or_else(|steps| (*steps).clone())
+    }
 
-        fn on_success(&self, ctx: &mut Context) {
-            eprintln!(
-                "Successfully fetched: {} in {}\n\nURL: {}\nBody: {:?}",
-                ctx.get_current_step().unwrap(),
-                ctx.get_time_elapsed_as_string(),
-                ctx.get_url(),
-                ctx.body_text().unwrap(),
-            );
+    /// Clears everything accumulated by previous runs, so the worker can be reused for an
+    /// independent one: stats, the body hashes of unchanged responses, the requests seen, a pending
+    /// rate limit pause, the health checks of the rotating proxies, and the context with its cookies, sessions, state
+    /// and last response. Registered steps and
+    /// configuration are kept, including a client injected with `HttpRequester::from_client`.
+    pub fn reset(&mut self) {
+        // back to the jar in use before the first session was selected
+        if let Some(default) = self.sessions.remove("default") {
+            self.ctx.http_requester_mut().set_cookie_store(default);
         }
+        self.ctx.reset();
+        self.stats = StatsRecorder::default();
+        self.last_hashes.clear();
+        self.seen_requests.clear();
+        self.sessions.clear();
+        self.rate_limited_until = None;
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
+    }
 
-        fn on_error(&self, _ctx: &mut Context, _err: StepError) {
-            todo!()
+    /// Creates a worker sharing this one's steps and concurrency limit, with its own fresh
+    /// context and cookie store, to run the same pipeline for several inputs at once.
+    /// Sessions selected with `with_session` aren't carried over.
+    /// Client settings and the default error handler are copied. Steps added to either worker
+    /// afterwards aren't seen by the other.
+    /// A client injected with `HttpRequester::from_client` isn't inherited, since it keeps the
+    /// cookie store it was built with: forks build their own clients from the copied settings.
+    pub fn fork(&self) -> Worker {
+        let mut ctx = Context::new();
+        *ctx.client_settings_mut() = self.ctx.client_settings().clone();
+        ctx.set_default_error_handler(self.ctx.default_error_handler());
+        ctx.set_always_default_error_handler(self.ctx.always_default_error_handler());
+        *ctx.limits_mut() = self.ctx.limits().clone();
+        ctx.http_requester_mut()
+            .set_header_transform(self.ctx.http_requester().header_transform());
+        ctx.http_requester_mut()
+            .set_url_normalizer(self.ctx.http_requester().url_normalizer());
+        #[cfg(any(test, feature = "test-util"))]
+        ctx.http_requester_mut()
+            .set_mock_transport(self.ctx.http_requester().mock_transport());
+
+        Worker {
+            steps: Arc::clone(&self.steps),
+            ctx,
+            stats: StatsRecorder::default(),
+            skip_unchanged: self.skip_unchanged,
+            last_hashes: HashMap::new(),
+            skip_seen: self.skip_seen,
+            seen_requests: HashSet::new(),
+            cache: self.cache.as_ref().map(|_| ResponseCache::default()),
+            warmup: self.warmup.clone(),
+            teardown: self.teardown.clone(),
+            sessions: HashMap::new(),
+            adaptive_timeout: self.adaptive_timeout,
+            pause_on_rate_limit: self.pause_on_rate_limit,
+            rate_limited_until: None,
+            metrics: self.metrics.clone(),
+            #[cfg(feature = "wire-log")]
+            wire_logger: self.wire_logger.clone(),
         }
+    }
 
-        fn on_timeout(&self, _ctx: &mut Context) {
-            todo!()
+    // get the step by name
+    fn get_step(&self, name: &str) -> Option<Arc<dyn Stepable>> {
+        match self.steps.get(name) {
+            Some(step) => Some(step.clone()),
+            None => None,
         }
     }
 
-    #[derive(Clone, Copy)]
-    struct SkippableStep;
+    /// Gets the context of the last step run, with its response, state and client settings.
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
 
-    #[async_trait]
-    impl Stepable for SkippableStep {
-        fn name(&self) -> String {
-            String::from(SKIPPABLE_STEP)
+    /// Gets the context mutably, e.g. to configure client settings or seed state before a run.
+    pub fn context_mut(&mut self) -> &mut Context {
+        &mut self.ctx
+    }
+
+    /// Returns per-step run and error counts along with p50/p95/p99 latencies
+    /// for every step run by this worker so far.
+    pub fn stats(&self) -> RunStats {
+        self.stats.summarize()
+    }
+
+    /// Returns the max, mean and p95 size in bytes of every response body read by this worker.
+    pub fn body_size_stats(&self) -> SizeStats {
+        self.stats.body_sizes()
+    }
+
+    /// Describes the registered steps as a Mermaid flowchart, with an edge from each step listed
+    /// in `Stepable::depends_on` to its dependant. Dependencies that aren't registered are drawn
+    /// with a dashed border. Cycles are drawn as they are.
+    pub fn describe(&self) -> String {
+        let keys = self.steps.keys();
+        let mut ids: HashMap<String, String> = HashMap::new();
+        let mut out = String::from("graph TD\n");
+
+        for (i, key) in keys.iter().enumerate() {
+            let id = format!("s{}", i);
+            let name = self
+                .steps
+                .get(key)
+                .map(|step| step.name())
+                .unwrap_or_default();
+            out.push_str(&format!("    {}[\"{}\"]\n", id, mermaid_label(&name)));
+            ids.insert(key.clone(), id);
         }
 
-        fn on_request(&self) -> Request {
-            Request::new(Method::GET, "https://google.com".to_string())
-                .skip_to(Some(ROBOTS_TXT.to_string()))
+        let mut edges = Vec::new();
+        let mut missing = Vec::new();
+        for key in &keys {
+            let step = match self.steps.get(key) {
+                Some(step) => step,
+                None => continue,
+            };
+            for dependency in step.depends_on() {
+                if !ids.contains_key(&dependency) {
+                    let id = format!("s{}", ids.len());
+                    out.push_str(&format!("    {}[\"{}\"]\n", id, mermaid_label(&dependency)));
+                    missing.push(id.clone());
+                    ids.insert(dependency.clone(), id);
+                }
+                edges.push(format!("    {} --> {}\n", ids[&dependency], ids[key]));
+            }
         }
 
-        fn on_success(&self, _ctx: &mut Context) {
-            todo!("This step should never be called")
+        for edge in edges {
+            out.push_str(&edge);
         }
+        for id in missing {
+            out.push_str(&format!("    style {} stroke-dasharray: 5 5\n", id));
+        }
+        out
+    }
 
-        fn on_error(&self, _ctx: &mut Context, _err: StepError) {
-            todo!("This step should never be called")
+    /// Runs the pipeline starting at `start`, following each step's next step until none is set,
+    /// and returns a report of every step executed.
+    /// A failing step ends the run unless its `on_error` or `on_timeout` sets a next step.
+    pub async fn run(&mut self, start: &str) -> RunReport {
+        let stop_watch = Instant::now();
+        let mut report = RunReport::default();
+
+        if let Some(warmup) = self.warmup.clone() {
+            if let Some(outcome) = self.run_hook("warmup", &warmup) {
+                report.push(outcome);
+                report.total_elapsed = stop_watch.elapsed();
+                return report;
+            }
         }
 
-        fn on_timeout(&self, _ctx: &mut Context) {
-            todo!("This step should never be called")
+        let mut next = Some(start.to_string());
+        while let Some(name) = next {
+            report.push(self.try_step_outcome(&name).await);
+            next = self.ctx.get_next_step();
         }
-    }
 
-    #[test]
-    fn it_should_add_step() {
-        let mut worker = Worker::new();
-        worker.add_step(RobotsTxt);
+        if let Some(teardown) = self.teardown.clone() {
+            if let Some(outcome) = self.run_hook("teardown", &teardown) {
+                report.push(outcome);
+            }
+        }
 
-        assert_eq!(worker.steps().len(), 1);
+        report.total_elapsed = stop_watch.elapsed();
+        report
     }
 
-    #[test]
-    fn it_should_add_step_arc() {
-        let mut worker = Worker::new();
-        let step = Arc::new(RobotsTxt);
-        worker.add_step_arc(step);
+    // runs a warmup or teardown hook, returning its outcome only if it failed
+    fn run_hook(&mut self, name: &str, hook: &RunHook) -> Option<StepOutcome> {
+        let stop_watch = Instant::now();
+        let error = hook(&mut self.ctx).err()?;
 
-        assert_eq!(worker.steps().len(), 1);
+        Some(StepOutcome {
+            name: name.to_string(),
+            status: None,
+            elapsed: stop_watch.elapsed(),
+            error: Some(error),
+        })
     }
 
-    #[test]
-    fn it_should_get_step() {
-        let mut worker = Worker::new();
-        worker.add_step(RobotsTxt);
+    /// Pulls step names from `rx` and runs them on up to `concurrency` lanes at once,
+    /// sending each outcome to `tx`, until `rx` is closed and drained.
+    /// A name is only pulled once a lane is free, so a bounded channel pushes back on its producer.
+    /// Each lane has its own context sharing this worker's client settings and cookie store,
+    /// and next steps set by the hooks are not followed.
+    pub async fn run_queue(
+        &mut self,
+        rx: Receiver<String>,
+        tx: Sender<StepOutcome>,
+        concurrency: usize,
+    ) {
+        let rx = Mutex::new(rx);
+        let mut lanes: Vec<Worker> = (0..concurrency.max(1)).map(|_| self.lane()).collect();
 
-        let step = worker.get_step(ROBOTS_TXT).unwrap();
-        assert_eq!(step.name(), ROBOTS_TXT);
+        futures_util::future::join_all(lanes.iter_mut().map(|lane| {
+            let (rx, tx) = (&rx, tx.clone());
+            async move {
+                loop {
+                    let name = match rx.lock().await.recv().await {
+                        Some(name) => name,
+                        None => break,
+                    };
+                    let outcome = lane.try_step_outcome(&name).await;
+                    // keep draining the queue even if nobody listens for outcomes anymore
+                    let _ = tx.send(outcome).await;
+                }
+            }
+        }))
+        .await;
+
+        for lane in lanes {
+            self.stats.merge(lane.stats);
+        }
     }
 
-    #[test]
-    fn it_should_call_on_request() {
-        let mut worker = Worker::new();
-        worker.add_step(RobotsTxt);
+    // a fork sharing this worker's cookie store as well
+    fn lane(&self) -> Worker {
+        let mut lane = self.fork();
+        lane.ctx
+            .set_http_requester(self.ctx.http_requester().clone());
+        lane
+    }
 
-        let step = worker.get_step(ROBOTS_TXT).unwrap();
-        let req = step.on_request();
-        assert_eq!(req.method(), "GET");
+    /// Runs every registered step once, in order of their keys, and returns a report of them.
+    /// Stops early, with the steps run so far, once more than `max_failures` steps failed.
+    /// `None` runs every step regardless of failures. Next steps set by the hooks are not followed.
+    pub async fn run_all(&mut self, max_failures: Option<usize>) -> RunReport {
+        let stop_watch = Instant::now();
+        let mut report = RunReport::default();
+
+        for key in self.steps.keys() {
+            report.push(self.try_step_outcome(&key).await);
+            if max_failures.is_some_and(|max| report.failures > max) {
+                break;
+            }
+        }
+
+        report.total_elapsed = stop_watch.elapsed();
+        report
     }
 
-    #[test]
-    fn it_should_update_context_from_request() {
-        let mut worker = Worker::new();
-        worker.add_step(RobotsTxt);
+    /// Sends a `HEAD` request to each of `urls` at once, so the connections they open are pooled
+    /// and reused by the steps that follow instead of being set up on the first request.
+    /// Only requests built with the same client, i.e. without their own proxy or user agent,
+    /// reuse them. Every url is tried; the first request that couldn't be sent is returned as
+    /// an error, whatever the status codes.
+    pub async fn warm_connections(&self, urls: &[&str]) -> Result<(), StepError> {
+        let http = self.ctx.http_requester();
+        let limits = self.ctx.limits();
+        let results = futures_util::future::join_all(urls.iter().map(|url| async move {
+            let _permits = limits
+                .acquire(&http.normalize_url(url))
+                .await
+                .map_err(|err| StepError::Other(err.to_string()))?;
+            let req = Request::new(Method::HEAD, url.to_string());
+            let builder = http
+                .build_reqwest_undecoded(req)
+                .map_err(|err| StepError::ReqwestError(err.to_string()))?;
+            http.send(builder)
+                .await
+                .map_err(|err| StepError::ReqwestError(err.to_string()))?;
+            Ok(())
+        }))
+        .await;
 
-        let step = worker.get_step(ROBOTS_TXT).unwrap();
-        let req = step.on_request();
+        results.into_iter().collect()
+    }
 
-        match worker.ctx.update_from_request(req) {
-            Ok(_) => {
-                assert_eq!(worker.ctx.get_method(), "GET");
-                assert_eq!(worker.ctx.get_url(), "https://google.com");
+    /// Runs the step `name` until `pred` returns true for the context it leaves behind, sleeping
+    /// `interval` between runs, e.g. to poll a job until its status is done. Gives up after
+    /// `max_iters` runs, failed ones included. Returns the context of the last run.
+    pub async fn run_until(
+        &mut self,
+        name: &str,
+        pred: impl Fn(&Context) -> bool,
+        interval: Duration,
+        max_iters: usize,
+    ) -> &Context {
+        for iteration in 0..max_iters {
+            if iteration > 0 {
+                tokio::time::sleep(interval).await;
             }
-            Err(e) => {
-                println!("Error: {}", e);
-                unreachable!()
+
+            let _ = self.try_step(name).await;
+            if pred(&self.ctx) {
+                break;
             }
         }
+
+        &self.ctx
     }
 
-    #[test]
-    fn it_should_update_current_step() {
-        let mut worker = Worker::new();
-        worker.ctx.set_current_step(ROBOTS_TXT.to_string());
-        assert_eq!(worker.ctx.get_current_step().unwrap(), ROBOTS_TXT);
+    /// Runs every registered step `pred` returns true for once, in order of their keys, and
+    /// returns the key and result of each. Next steps set by the hooks are not followed.
+    pub async fn run_where(
+        &mut self,
+        pred: impl Fn(&dyn Stepable) -> bool,
+    ) -> Vec<(String, Result<(), StepError>)> {
+        let mut results = Vec::new();
+
+        for key in self.steps.keys() {
+            let selected = self.steps.get(&key).is_some_and(|step| pred(step.as_ref()));
+            if !selected {
+                continue;
+            }
+
+            let outcome = self.try_step_outcome(&key).await;
+            results.push((key, outcome.error.map_or(Ok(()), Err)));
+        }
+
+        results
     }
 
-    /// This actually goes to https://google.com and fetches the page.
-    /// It's ignored because it can break if the internet is down.
-    /// It's here for testing purposes only.
-    #[tokio::test]
-    #[ignore]
-    async fn it_should_try_step() {
-        let mut worker = Worker::new();
-        worker.add_step(RobotsTxt);
+    /// Runs each of the steps `names` once, in order, and returns the body of each one that
+    /// succeeded keyed by its name. Failed steps are left out, and next steps set by the hooks
+    /// are not followed.
+    pub async fn run_collect(&mut self, names: &[&str]) -> HashMap<String, Bytes> {
+        let mut bodies = HashMap::new();
 
-        match worker.try_step(ROBOTS_TXT).await {
-            Ok(_) => {
-                assert_eq!(worker.ctx.get_method(), "GET");
-                assert_eq!(worker.ctx.get_url(), "https://google.com");
+        for name in names {
+            let outcome = self.try_step_outcome(name).await;
+            if outcome.error.is_some() {
+                continue;
             }
-            Err(e) => {
-                println!("Error: {}", e);
-                assert!(false);
+            if let Ok(body) = self.ctx.body_bytes() {
+                bodies.insert(name.to_string(), body);
             }
         }
+
+        bodies
     }
 
-    #[test]
-    fn check_status_codes_should_return_true_if_status_code_matches() {
-        let mut worker = Worker::new();
-        worker.ctx.set_status_codes(vec![200]);
+    pub async fn try_step(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let stop_watch = Instant::now();
+        let result = self.run_step(name).await;
+        self.stats.record_run(name, result.is_ok());
 
-        assert!(worker.check_status_code(200));
+        if let Some(metrics) = &self.metrics {
+            let outcome = self.outcome(name, stop_watch.elapsed(), &result);
+            let labels = self
+                .get_step(name)
+                .map(|step| step.metric_labels())
+                .unwrap_or_default();
+            metrics.record_step(&outcome, &labels);
+        }
+        result
     }
 
-    #[test]
-    fn check_status_codes_should_return_false_if_status_code_does_not_match() {
-        let mut worker = Worker::new();
-        worker.ctx.set_status_codes(vec![200]);
+    /// Runs the step `name` and deserializes each line of its newline-delimited JSON body as it
+    /// arrives, handing the items to `on_item` one at a time. The body is read no faster than
+    /// `on_item` handles the items. Blank lines are skipped, and the stream stops at the first
+    /// line that isn't valid JSON or the first error returned by `on_item`.
+    ///
+    /// The status code and content type are checked as for `try_step`, but the step isn't
+    /// retried, its hooks don't run and the body isn't kept in the context. An uncompressed body
+    /// is asked for since compressed ones can't be split into lines as they arrive.
+    #[cfg(feature = "json")]
+    pub async fn stream_ndjson<T: DeserializeOwned>(
+        &mut self,
+        name: &str,
+        mut on_item: impl FnMut(T) -> Result<(), StepError>,
+    ) -> Result<(), StepError> {
+        let (mut res, _permits) = self.open_stream(name).await?;
 
-        assert!(!worker.check_status_code(404));
+        let mut handle = |line: Vec<u8>| {
+            if line.iter().all(u8::is_ascii_whitespace) {
+                return Ok(());
+            }
+            let item = serde_json::from_slice(&line)
+                .map_err(|err| StepError::Other(format!("Invalid NDJSON line: {}", err)))?;
+            on_item(item)
+        };
+
+        let mut lines = LineBuffer::default();
+        while let Some(chunk) = read_chunk(&mut res).await? {
+            for line in lines.push(&chunk) {
+                handle(line)?;
+            }
+        }
+        match lines.finish() {
+            Some(line) => handle(line),
+            None => Ok(()),
+        }
     }
 
-    #[test]
-    fn check_status_codes_should_use_default_status_codes_if_200_to_300_if_status_cares_are_empty()
-    {
-        let mut worker = Worker::new();
-        worker.ctx.set_status_codes(vec![]);
+    /// Runs the step `name` and parses its `text/event-stream` body as it arrives, handing each
+    /// Server-Sent Event to `on_event` until the server closes the stream. Like `stream_ndjson`,
+    /// the step isn't retried and its hooks don't run.
+    #[cfg(feature = "sse")]
+    pub async fn stream_sse(
+        &mut self,
+        name: &str,
+        mut on_event: impl FnMut(crate::SseEvent),
+    ) -> Result<(), StepError> {
+        let (mut res, _permits) = self.open_stream(name).await?;
 
-        assert!(worker.check_status_code(200));
+        let mut lines = LineBuffer::default();
+        let mut parser = crate::stream::SseParser::default();
+        while let Some(chunk) = read_chunk(&mut res).await? {
+            for line in lines.push(&chunk) {
+                if let Some(event) = parser.line(&line) {
+                    on_event(event);
+                }
+            }
+        }
+        // an event the stream didn't end with a blank line is incomplete and dropped
+        Ok(())
     }
 
-    #[test]
+    // sends the step's request and checks its response, leaving the body to be read as a stream
+    async fn open_stream(
+        &mut self,
+        name: &str,
+    ) -> Result<(Response, Vec<OwnedSemaphorePermit>), StepError> {
+        self.ctx.clear_next_step();
+        let step = self
+            .get_step(name)
+            .ok_or_else(|| StepError::StepNotFound(name.to_string()))?;
+
+        let req = self.build_request(&step, name, 1).await;
+        self.ctx.clear_response();
+        self.load_request(req.with_accept_encoding(""))
+            .map_err(|err| StepError::Other(err.to_string()))?;
+        self.ctx.set_current_step(name.to_string());
+
+        let permits = self
+            .acquire_permits()
+            .await
+            .map_err(|err| StepError::Other(err.to_string()))?;
+
+        let req_builder = self.ctx.get_request_builder().unwrap();
+        let stop_watch = Instant::now();
+        let res = self
+            .ctx
+            .http_requester()
+            .send(req_builder)
+            .await
+            .map_err(|err| {
+                if err.is_timeout() {
+                    let phase = err.timeout_phase();
+                    return StepError::Timeout { phase };
+                }
+                StepError::ReqwestError(err.to_string())
+            })?;
+        self.ctx
+            .set_time_elapsed(stop_watch.elapsed().as_millis() as u64);
+        self.ctx.set_status_code(res.status().as_u16());
+        self.ctx.set_response_headers(res.headers().clone());
+
+        if !self.check_status_code(res.status().as_u16()) {
+            return Err(StepError::StatusCodeNotFound(
+                res.status().as_u16() as i32,
+                self.ctx.get_status_codes().unwrap_or_default(),
+            ));
+        }
+        self.check_content_type()?;
+        if let Some(encoding) = res.headers().get(CONTENT_ENCODING) {
+            if encoding != "identity" {
+                return Err(StepError::Other(format!(
+                    "Compressed streams aren't supported: {:?}",
+                    encoding
+                )));
+            }
+        }
+
+        Ok((res, permits))
+    }
+
+    // waits for the permits the loaded request needs, see `Limits::acquire`
+    async fn acquire_permits(&self) -> Result<Vec<OwnedSemaphorePermit>, AcquireError> {
+        let url = self.ctx.http_requester().normalize_url(&self.ctx.get_url());
+        self.ctx.limits().acquire(&url).await
+    }
+
+    // runs the step and captures the result as an outcome instead of an error
+    async fn try_step_outcome(&mut self, name: &str) -> StepOutcome {
+        let stop_watch = Instant::now();
+        let result = self.try_step(name).await;
+        self.outcome(name, stop_watch.elapsed(), &result)
+    }
+
+    fn outcome(
+        &self,
+        name: &str,
+        elapsed: Duration,
+        result: &Result<(), Box<dyn std::error::Error>>,
+    ) -> StepOutcome {
+        StepOutcome {
+            name: name.to_string(),
+            status: self.ctx.get_status_code(),
+            elapsed,
+            error: result
+                .as_ref()
+                .err()
+                .map(|err| StepError::from_error(err.as_ref())),
+        }
+    }
+
+    // runs attempts of the step until one succeeds or its retries are used up,
+    // then reports the last failure to the step's hooks
+    async fn run_step(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        // clear the next step since the context is being reused, this fixes the infinite loop bug
+        self.ctx.clear_next_step();
+
+        let step = match self.get_step(name) {
+            Some(step) => step,
+            None => return Err(Box::new(StepError::StepNotFound(name.to_string()))),
+        };
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let failure = match self.attempt_step(&step, name, attempts).await {
+                Ok(()) => return Ok(()),
+                Err(failure) => failure,
+            };
+
+            // a timed-out attempt took at least its timeout, and sampling that lets an adaptive
+            // timeout that shrank below the actual latency grow back
+            if let Failure::Timeout(_) = failure {
+                let timeout = self.ctx.get_request().timeout();
+                let timeout = timeout.unwrap_or(Duration::from_secs(30));
+                self.stats.record_latency(name, timeout.as_millis() as u64);
+            }
+
+            let (failure, retry_after) = match failure {
+                Failure::RetryStatus(error, retry_after) => (Failure::step(error), retry_after),
+                failure => (failure, None),
+            };
+
+            let max_attempts = self.ctx.get_request().max_attempts();
+            match failure {
+                Failure::Aborted(err) => return Err(err),
+                _ if attempts < max_attempts => {
+                    if let Some(delay) = self.retry_delay(retry_after) {
+                        tokio::time::sleep(delay).await;
+                    }
+                }
+                Failure::Timeout(err) if max_attempts > 1 => {
+                    let last = StepError::from_error(err.as_ref());
+                    self.report_error(&step, last, Hook::RetryExhausted(attempts));
+                    return Err(err);
+                }
+                Failure::Error(error, err) if max_attempts > 1 => {
+                    self.report_error(&step, error, Hook::RetryExhausted(attempts));
+                    return Err(err);
+                }
+                Failure::Timeout(err) => {
+                    let error = StepError::from_error(err.as_ref());
+                    self.report_error(&step, error, Hook::Timeout);
+                    return Err(err);
+                }
+                Failure::Error(error, err) => {
+                    self.report_error(&step, error, Hook::Error);
+                    return Err(err);
+                }
+                Failure::RetryStatus(..) => unreachable!("converted to an error above"),
+            }
+        }
+    }
+
+    // how long to wait before the next attempt: as long as a `Retry-After` asked, up to the
+    // settings' max, or the request's backoff without one
+    fn retry_delay(&self, retry_after: Option<Duration>) -> Option<Duration> {
+        let max = self.ctx.client_settings().max_retry_after();
+        retry_after
+            .map(|delay| delay.min(max))
+            .or(self.ctx.get_request().retry_backoff())
+    }
+
+    // how long to hold back the next request when the last response has no requests left: until
+    // `X-RateLimit-Reset`, up to the settings' max `Retry-After`
+    fn rate_limit_pause(&self) -> Option<Duration> {
+        // counts of seconds are far below any unix time of the last two decades
+        const UNIX_TIME_FROM: u64 = 1_000_000_000;
+
+        if self.ctx.rate_limit_remaining()? > 0 {
+            return None;
+        }
+        let reset = self.ctx.rate_limit_reset()?;
+        let secs = if reset >= UNIX_TIME_FROM {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs());
+            reset.saturating_sub(now)
+        } else {
+            reset
+        };
+        let max = self.ctx.client_settings().max_retry_after();
+        Some(Duration::from_secs(secs).min(max))
+    }
+
+    // hands a failure to the step's hook for it, and to the default error handler when it sees
+    // every error
+    fn report_error(&mut self, step: &Arc<dyn Stepable>, error: StepError, hook: Hook) {
+        self.ctx.set_last_error(error.clone());
+        let always = match self.ctx.default_error_handler() {
+            Some(handler) if self.ctx.always_default_error_handler() => {
+                Some((handler, error.clone()))
+            }
+            _ => None,
+        };
+
+        match hook {
+            Hook::Error => step.on_error(&mut self.ctx, error),
+            Hook::Timeout => step.on_timeout(&mut self.ctx),
+            Hook::RetryExhausted(attempts) => {
+                step.on_retry_exhausted(&mut self.ctx, attempts, error)
+            }
+        }
+
+        if let Some((handler, error)) = always {
+            handler(&step.name(), &error);
+        }
+    }
+
+    // builds the step's request with the step's proxy, the adaptive timeout and the timeout
+    // growth of the attempt applied
+    async fn build_request(&self, step: &Arc<dyn Stepable>, name: &str, attempt: u32) -> Request {
+        let req = step.on_request_async(&self.ctx).await;
+        let req = step.before_request(&self.ctx, req);
+        let req = match step.proxy() {
+            Some(proxy) if req.proxy().is_none() => req.with_proxy(proxy),
+            _ => req,
+        };
+        let adapted = self
+            .adaptive_timeout
+            .and_then(|adaptive| adaptive.timeout_for(self.stats.latencies(name)));
+        let req = match adapted {
+            Some(timeout) => req.with_timeout(timeout),
+            None => req,
+        };
+        match (req.timeout(), req.timeout_growth()) {
+            (Some(timeout), Some(max)) if attempt > 1 => {
+                let grown = timeout.saturating_mul(attempt).min(max).max(timeout);
+                req.with_timeout(grown)
+            }
+            _ => req,
+        }
+    }
+
+    // updates the context from the request, building its client with the jar of the request's
+    // own session if it has one
+    fn load_request(&mut self, req: Request) -> Result<(), Box<dyn std::error::Error>> {
+        let session = match req.session() {
+            Some(session) => session.to_string(),
+            None => return self.ctx.update_from_request(req),
+        };
+
+        let jar = self.session_jar(&session);
+        let current = self.ctx.http_requester().cookie_store();
+        self.ctx.http_requester_mut().set_cookie_store(jar);
+        let updated = self.ctx.update_from_request(req);
+        self.ctx.http_requester_mut().set_cookie_store(current);
+        updated
+    }
+
+    // start the instant timer to run the step
+    // run send() on the request_builder
+    // stop the instant timer
+    async fn attempt_step(
+        &mut self,
+        step: &Arc<dyn Stepable>,
+        name: &str,
+        attempt: u32,
+    ) -> Result<(), Failure> {
+        let req = self.build_request(step, name, attempt).await;
+        self.ctx.clear_response();
+
+        if self.skip_seen {
+            let seen = self.seen_key(&req);
+            if seen.is_some_and(|seen| self.seen_requests.contains(&seen)) {
+                return Ok(());
+            }
+        }
+
+        if req.get_skip_to_step().is_some() {
+            self.ctx
+                .set_next_step(req.get_skip_to_step().unwrap().clone());
+            return Ok(());
+        }
+
+        self.load_request(req).map_err(Failure::Aborted)?;
+        self.ctx.set_current_step(name.to_string());
+
+        let is_get = self.ctx.get_request().method_ref() == Method::GET;
+        let cached_as = self
+            .cache
+            .is_some()
+            .then(|| self.cache_key())
+            .filter(|_| is_get);
+        if let Some(cached) = cached_as
+            .as_ref()
+            .and_then(|(key, headers)| self.cache.as_mut()?.get(key, headers))
+        {
+            self.ctx.set_time_elapsed(0);
+            self.ctx.set_status_code(cached.status);
+            self.ctx.set_response_headers(cached.headers.clone());
+            self.ctx
+                .set_response_body(step.transform_body(cached.body.clone()));
+            return self.finish_step(step, name);
+        }
+
+        let req_builder = self.ctx.get_request_builder().unwrap();
+
+        // waits before taking the permits, so other hosts aren't held up
+        if let Some(until) = self.rate_limited_until.take() {
+            tokio::time::sleep_until(until.into()).await;
+        }
+
+        let _permits = self
+            .acquire_permits()
+            .await
+            .map_err(|err| Failure::Aborted(Box::new(err)))?;
+
+        #[cfg(feature = "wire-log")]
+        self.log_request(&req_builder);
+
+        // Start processing the request and time it.
+        let stop_watch = std::time::Instant::now();
+        let mut res = match self.ctx.http_requester().send(req_builder).await {
+            Ok(res) => res,
+            Err(err) => {
+                if err.is_timeout() {
+                    let phase = err.timeout_phase();
+                    return Err(Failure::Timeout(Box::new(StepError::Timeout { phase })));
+                }
+
+                let error = StepError::ReqwestError(err.to_string());
+                return Err(Failure::Error(error, err.into_boxed()));
+            }
+        };
+        self.ctx
+            .set_time_elapsed(stop_watch.elapsed().as_millis() as u64);
+        self.stats.record_latency(name, self.ctx.get_time_elapsed());
+        self.ctx.set_status_code(res.status().as_u16());
+        self.ctx.set_http_version(res.version());
+        self.ctx.set_response_headers(res.headers().clone());
+        if self.pause_on_rate_limit {
+            self.rate_limited_until = self.rate_limit_pause().map(|pause| Instant::now() + pause);
+        }
+        if let Some(etag) = res.headers().get(ETAG).and_then(|v| v.to_str().ok()) {
+            self.ctx.set_etag(etag.to_string());
+        }
+
+        // an unchanged resource answering a conditional request isn't an error
+        let not_modified = res.status() == StatusCode::NOT_MODIFIED
+            && self.ctx.get_request().if_none_match().is_some();
+        self.ctx.set_not_modified(not_modified);
+
+        let status = res.status().as_u16();
+        if self.ctx.get_request().retry_on_status().contains(&status) {
+            let error = StepError::StatusCodeNotFound(
+                status as i32,
+                self.ctx.get_status_codes().unwrap_or_default(),
+            );
+            return Err(Failure::RetryStatus(error, retry_after(res.headers())));
+        }
+
+        if !not_modified && !self.check_status_code(res.status().as_u16()) {
+            let error = StepError::StatusCodeNotFound(
+                res.status().as_u16() as i32,
+                self.ctx.get_status_codes().unwrap_or_default(),
+            );
+
+            return Err(Failure::step(error));
+        }
+
+        if !not_modified {
+            self.check_content_type().map_err(Failure::step)?;
+        }
+
+        // Read the body chunk by chunk so its size can be capped as it grows, the decompressed
+        // size is capped again while decoding.
+        let limit = self.ctx.client_settings().max_decompressed_bytes();
+        let mut body = bytes::BytesMut::new();
+        let total = res.content_length();
+        let mut reported = 0;
+        loop {
+            let chunk = match res.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(err) if err.is_timeout() => {
+                    let phase = TimeoutPhase::from_reqwest(&err, true);
+                    return Err(Failure::Timeout(Box::new(StepError::Timeout { phase })));
+                }
+                Err(err) => {
+                    let error = StepError::ReqwestError(err.to_string());
+                    return Err(Failure::Error(error, Box::new(err)));
+                }
+            };
+
+            if let Some(limit) = limit {
+                if body.len() + chunk.len() > limit {
+                    return Err(Failure::step(StepError::DecompressionLimitExceeded(limit)));
+                }
+            }
+            body.extend_from_slice(&chunk);
+
+            if body.len() as u64 - reported >= PROGRESS_INTERVAL {
+                reported = body.len() as u64;
+                step.on_progress(reported, total);
+            }
+        }
+        if body.len() as u64 != reported {
+            step.on_progress(body.len() as u64, total);
+        }
+
+        #[cfg(feature = "wire-log")]
+        if let Some(logger) = &self.wire_logger {
+            let bytes = wire::response_bytes(res.version(), res.status(), res.headers(), &body);
+            logger(WireEvent::ResponseReceived(bytes));
+        }
+
+        // the announced length is the one of the body as sent, before decompression
+        self.check_content_length(body.len())
+            .map_err(Failure::step)?;
+        self.ctx.set_transfer_size(body.len() as u64);
+        let body = self
+            .ctx
+            .http_requester()
+            .decode_body(res.headers(), body.freeze(), limit)
+            .map_err(Failure::step)?;
+        self.stats.record_body_size(body.len());
+        if let Some(min) = self.ctx.get_request().min_body_size() {
+            if !not_modified && body.len() < min {
+                let got = body.len();
+                return Err(Failure::step(StepError::BodyTooSmall { got, min }));
+            }
+        }
+
+        let cached_body = body.clone();
+        self.ctx.set_response_body(step.transform_body(body));
+
+        self.finish_step(step, name)?;
+
+        // only successful responses the step accepted are kept, so none skips its checks later
+        if let (Some(cache), Some((key, headers))) = (self.cache.as_mut(), cached_as) {
+            if !not_modified && is_default_success(status, false) {
+                cache.insert(key, &headers, status, res.headers(), cached_body);
+            }
+        }
+        Ok(())
+    }
+
+    // the cookie jar a request is sent with: the one of its session, or the one in use. `None`
+    // for a session that wasn't created yet
+    fn request_jar(&self, req: &Request) -> Option<Arc<CookieJar>> {
+        match req.session() {
+            Some(session) => self.sessions.get(session).cloned(),
+            None => Some(self.ctx.http_requester().cookie_store()),
+        }
+    }
+
+    // what a request is remembered under by `skip_seen_requests`, so sessions don't share it
+    fn seen_key(&self, req: &Request) -> Option<(usize, u64)> {
+        let jar = self.request_jar(req)?;
+        Some((Arc::as_ptr(&jar) as usize, req.fingerprint()))
+    }
+
+    // what the loaded request's response is cached under, with the headers it is sent with
+    fn cache_key(&self) -> (CacheKey, HeaderMap) {
+        let jar = self
+            .request_jar(self.ctx.get_request())
+            .unwrap_or_else(|| self.ctx.http_requester().cookie_store());
+        let url = self.ctx.http_requester().normalize_url(&self.ctx.get_url());
+        let headers = self
+            .ctx
+            .sent_request()
+            .map(|sent| sent.headers.clone())
+            .unwrap_or_default();
+        let key = CacheKey::new(&url, Arc::as_ptr(&jar) as usize, &headers);
+        (key, headers)
+    }
+
+    // hands the request about to be sent to the wire logger, with the body the builder holds
+    #[cfg(feature = "wire-log")]
+    fn log_request(&self, builder: &reqwest::RequestBuilder) {
+        let (Some(logger), Some(sent)) = (&self.wire_logger, self.ctx.sent_request()) else {
+            return;
+        };
+        let built = builder.try_clone().and_then(|builder| builder.build().ok());
+        let body = built
+            .as_ref()
+            .and_then(|built| built.body())
+            .and_then(|body| body.as_bytes());
+        logger(WireEvent::RequestSent(wire::request_bytes(sent, body)));
+    }
+
+    // hands a successful response to the step
+    fn finish_step(&mut self, step: &Arc<dyn Stepable>, name: &str) -> Result<(), Failure> {
+        #[cfg(feature = "json")]
+        if !self.ctx.not_modified() {
+            self.check_json_kind().map_err(Failure::step)?;
+        }
+
+        if self.skip_seen {
+            if let Some(seen) = self.seen_key(self.ctx.get_request()) {
+                self.seen_requests.insert(seen);
+            }
+        }
+
+        if self.skip_unchanged {
+            let hash = self.ctx.body_hash().unwrap_or_default();
+            if self.last_hashes.insert(name.to_string(), hash) == Some(hash) {
+                return Ok(());
+            }
+        }
+        step.on_success(&mut self.ctx);
+
+        match step.on_success_action(&mut self.ctx) {
+            FlowControl::Continue => {}
+            FlowControl::Halt => self.ctx.clear_next_step(),
+            FlowControl::GoTo(next) => self.ctx.set_next_step(next),
+        }
+
+        Ok(())
+    }
+
+    // listed status codes and ranges are the only ones accepted, else `is_default_success` decides
+    fn check_status_code(&self, status_code: u16) -> bool {
+        if self.ctx.get_request().accepts_any_2xx() && is_default_success(status_code, false) {
+            return true;
+        }
+
+        let ranges = self.ctx.get_request().status_ranges();
+        if ranges.iter().any(|range| range.contains(&status_code)) {
+            return true;
+        }
+
+        let settings = self.ctx.client_settings();
+        let redirects = settings.redirects_as_success();
+        match &self.ctx.get_status_codes() {
+            Some(codes) => {
+                if codes.is_empty() && ranges.is_empty() && settings.empty_status_codes_means_none()
+                {
+                    return is_default_success(status_code, redirects);
+                }
+                codes.contains(&status_code)
+            }
+            None if !ranges.is_empty() => false,
+            None => is_default_success(status_code, redirects),
+        }
+    }
+
+    // compares the downloaded byte count against the announced content length to detect truncated bodies
+    fn check_content_length(&self, downloaded: usize) -> Result<(), StepError> {
+        let expected = match self.ctx.content_length() {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+
+        // these responses announce a length without carrying a body
+        let bodiless = self.ctx.get_request().method_ref() == Method::HEAD
+            || matches!(self.ctx.get_status_code(), Some(204) | Some(304));
+        if bodiless || expected == downloaded as u64 {
+            return Ok(());
+        }
+
+        let mismatch = StepError::ContentLengthMismatch {
+            expected,
+            got: downloaded as u64,
+        };
+        match self.ctx.client_settings().content_length_check() {
+            ContentLengthCheck::Ignore => Ok(()),
+            ContentLengthCheck::Warn => {
+                if let Some(handler) = self.ctx.default_error_handler() {
+                    handler(&self.ctx.get_current_step().unwrap_or_default(), &mismatch);
+                }
+                Ok(())
+            }
+            ContentLengthCheck::Error => Err(mismatch),
+        }
+    }
+
+    // compares the response content type against the expected one, ignoring parameters like charset
+    fn check_content_type(&self) -> Result<(), StepError> {
+        let expected = match self.ctx.get_request().expected_content_type() {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+
+        let got = self
+            .ctx
+            .get_response_headers()
+            .and_then(|headers| headers.get(CONTENT_TYPE))
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+
+        let mime = got.split(';').next().unwrap_or_default().trim();
+        if mime.eq_ignore_ascii_case(expected.trim()) {
+            return Ok(());
+        }
+
+        Err(StepError::UnexpectedContentType { expected, got })
+    }
+
+    // parses the body to compare the kind of its JSON value against the expected one
+    #[cfg(feature = "json")]
+    fn check_json_kind(&self) -> Result<(), StepError> {
+        let expected = match self.ctx.get_request().expected_json_kind() {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+
+        let got = self
+            .ctx
+            .body_bytes()
+            .ok()
+            .and_then(|body| serde_json::from_slice::<serde_json::Value>(&body).ok())
+            .map(|value| JsonKind::of(&value));
+        if got == Some(expected) {
+            return Ok(());
+        }
+
+        Err(StepError::UnexpectedJsonKind { expected, got })
+    }
+}
+
+// reads the next chunk of a streamed body, None once it ended
+async fn read_chunk(res: &mut Response) -> Result<Option<Bytes>, StepError> {
+    res.chunk().await.map_err(|err| {
+        if err.is_timeout() {
+            let phase = TimeoutPhase::from_reqwest(&err, true);
+            return StepError::Timeout { phase };
+        }
+        StepError::ReqwestError(err.to_string())
+    })
+}
+
+// the delay a `Retry-After` header asks for, either in seconds or until an http date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    match value.parse::<u64>() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => {
+            let at = httpdate::parse_http_date(value).ok()?;
+            Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+        }
+    }
+}
+
+/// Whether a status counts as success for a request that doesn't list its status codes: 200 to
+/// 299 inclusive, and 300 to 399 inclusive as well when `redirects` are accepted.
+fn is_default_success(status_code: u16, redirects: bool) -> bool {
+    (200..=299).contains(&status_code) || (redirects && (300..=399).contains(&status_code))
+}
+
+// escapes the characters that would end a quoted Mermaid label
+fn mermaid_label(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_server::{TestResponse, TestServer};
+    use crate::worker::{retry_after, Worker};
+    use crate::{
+        AdaptiveTimeout, ContentLengthCheck, Context, DefaultUrlNormalizer, FlowControl, Metrics,
+        Request, StepError, StepOutcome, Stepable, TimeoutPhase, PROGRESS_INTERVAL,
+    };
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use reqwest::header::{HeaderMap, CONTENT_LENGTH, RETRY_AFTER};
+    use reqwest::Method;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    #[derive(Clone, Copy)]
+    struct RobotsTxt;
+
+    const ROBOTS_TXT: &str = "RobotsTxt";
+    const SKIPPABLE_STEP: &str = "SkippableStep";
+
+    #[async_trait]
+    impl Stepable for RobotsTxt {
+        fn name(&self) -> String {
+            String::from(ROBOTS_TXT)
+        }
+
+        fn on_request(&self) -> Request {
+            Request::new(Method::GET, "https://google.com".to_string())
+        }
+
+        fn on_success(&self, ctx: &mut Context) {
+            eprintln!(
+                "Successfully fetched: {} in {}\n\nURL: {}\nBody: {:?}",
+                ctx.get_current_step().unwrap(),
+                ctx.get_time_elapsed_as_string(),
+                ctx.get_url(),
+                ctx.body_text().unwrap(),
+            );
+        }
+
+        fn on_error(&self, _ctx: &mut Context, _err: StepError) {
+            todo!()
+        }
+
+        fn on_timeout(&self, _ctx: &mut Context) {
+            todo!()
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    struct SkippableStep;
+
+    #[async_trait]
+    impl Stepable for SkippableStep {
+        fn name(&self) -> String {
+            String::from(SKIPPABLE_STEP)
+        }
+
+        fn on_request(&self) -> Request {
+            Request::new(Method::GET, "https://google.com".to_string())
+                .skip_to(Some(ROBOTS_TXT.to_string()))
+        }
+
+        fn on_success(&self, _ctx: &mut Context) {
+            todo!("This step should never be called")
+        }
+
+        fn on_error(&self, _ctx: &mut Context, _err: StepError) {
+            todo!("This step should never be called")
+        }
+
+        fn on_timeout(&self, _ctx: &mut Context) {
+            todo!("This step should never be called")
+        }
+    }
+
+    /// A step that fetches a fixed url, used to run steps against the local test server.
+    struct FetchStep {
+        name: String,
+        request: Request,
+        next_step: Option<String>,
+    }
+
+    const FETCH_STEP: &str = "FetchStep";
+
+    impl FetchStep {
+        fn new(url: String) -> Self {
+            FetchStep::with_request(Request::new(Method::GET, url))
+        }
+
+        fn with_request(request: Request) -> Self {
+            FetchStep::named(FETCH_STEP, request)
+        }
+
+        fn named(name: &str, request: Request) -> Self {
+            FetchStep {
+                name: name.to_string(),
+                request,
+                next_step: None,
+            }
+        }
+
+        fn then(mut self, next_step: &str) -> Self {
+            self.next_step = Some(next_step.to_string());
+            self
+        }
+    }
+
+    #[async_trait]
+    impl Stepable for FetchStep {
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+
+        fn on_request(&self) -> Request {
+            self.request.clone()
+        }
+
+        fn on_success(&self, ctx: &mut Context) {
+            if let Some(next_step) = &self.next_step {
+                ctx.set_next_step(next_step.clone());
+            }
+        }
+
+        fn on_error(&self, _ctx: &mut Context, _err: StepError) {}
+
+        fn on_timeout(&self, _ctx: &mut Context) {}
+    }
+
+    /// A step that authenticates with the token fetched by the previous step.
+    struct AuthedStep {
+        url: String,
+    }
+
+    const AUTHED_STEP: &str = "AuthedStep";
+
+    #[async_trait]
+    impl Stepable for AuthedStep {
+        fn name(&self) -> String {
+            String::from(AUTHED_STEP)
+        }
+
+        fn on_request(&self) -> Request {
+            Request::new(Method::GET, self.url.clone())
+        }
+
+        fn before_request(&self, ctx: &Context, req: Request) -> Request {
+            let token = ctx.body_text().unwrap();
+            let mut headers = req.headers().unwrap_or_default();
+            headers.insert(
+                "Authorization",
+                format!("Bearer {}", token).parse().unwrap(),
+            );
+            req.with_headers(headers)
+        }
+
+        fn on_success(&self, _ctx: &mut Context) {}
+
+        fn on_error(&self, _ctx: &mut Context, _err: StepError) {}
+
+        fn on_timeout(&self, _ctx: &mut Context) {}
+    }
+
+    #[test]
+    fn it_should_add_step() {
+        let mut worker = Worker::new();
+        worker.add_step(RobotsTxt);
+
+        assert_eq!(worker.steps().len(), 1);
+    }
+
+    #[test]
+    fn it_should_add_step_arc() {
+        let mut worker = Worker::new();
+        let step = Arc::new(RobotsTxt);
+        worker.add_step_arc(step);
+
+        assert_eq!(worker.steps().len(), 1);
+    }
+
+    #[test]
+    fn it_should_get_step() {
+        let mut worker = Worker::new();
+        worker.add_step(RobotsTxt);
+
+        let step = worker.get_step(ROBOTS_TXT).unwrap();
+        assert_eq!(step.name(), ROBOTS_TXT);
+    }
+
+    #[test]
+    fn it_should_call_on_request() {
+        let mut worker = Worker::new();
+        worker.add_step(RobotsTxt);
+
+        let step = worker.get_step(ROBOTS_TXT).unwrap();
+        let req = step.on_request();
+        assert_eq!(req.method(), "GET");
+    }
+
+    #[test]
+    fn it_should_update_context_from_request() {
+        let mut worker = Worker::new();
+        worker.add_step(RobotsTxt);
+
+        let step = worker.get_step(ROBOTS_TXT).unwrap();
+        let req = step.on_request();
+
+        match worker.ctx.update_from_request(req) {
+            Ok(_) => {
+                assert_eq!(worker.ctx.get_method(), "GET");
+                assert_eq!(worker.ctx.get_url(), "https://google.com");
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                unreachable!()
+            }
+        }
+    }
+
+    #[test]
+    fn it_should_update_current_step() {
+        let mut worker = Worker::new();
+        worker.ctx.set_current_step(ROBOTS_TXT.to_string());
+        assert_eq!(worker.ctx.get_current_step().unwrap(), ROBOTS_TXT);
+    }
+
+    /// This actually goes to https://google.com and fetches the page.
+    /// It's ignored because it can break if the internet is down.
+    /// It's here for testing purposes only.
+    #[tokio::test]
+    #[ignore]
+    async fn it_should_try_step() {
+        let mut worker = Worker::new();
+        worker.add_step(RobotsTxt);
+
+        match worker.try_step(ROBOTS_TXT).await {
+            Ok(_) => {
+                assert_eq!(worker.ctx.get_method(), "GET");
+                assert_eq!(worker.ctx.get_url(), "https://google.com");
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                unreachable!()
+            }
+        }
+    }
+
+    #[test]
+    fn check_status_codes_should_return_true_if_status_code_matches() {
+        let mut worker = Worker::new();
+        worker.ctx.set_status_codes(vec![200]);
+
+        assert!(worker.check_status_code(200));
+    }
+
+    #[test]
+    fn check_status_codes_should_return_false_if_status_code_does_not_match() {
+        let mut worker = Worker::new();
+        worker.ctx.set_status_codes(vec![200]);
+
+        assert!(!worker.check_status_code(404));
+    }
+
+    #[test]
+    fn check_status_codes_should_use_default_status_codes_if_200_to_300_if_status_cares_are_empty()
+    {
+        let mut worker = Worker::new();
+        worker.ctx.set_status_codes(vec![]);
+
+        assert!(worker.check_status_code(200));
+    }
+
+    #[test]
+    fn check_status_codes_should_reject_everything_for_an_empty_list_when_strict() {
+        let mut worker = Worker::new();
+        worker.ctx.set_status_codes(vec![]);
+        assert!(worker.ctx.client_settings().empty_status_codes_means_none());
+
+        worker
+            .ctx
+            .client_settings_mut()
+            .set_empty_status_codes_means_none(false);
+        for status in [200, 204, 301, 404, 500] {
+            assert!(!worker.check_status_code(status), "{}", status);
+        }
+
+        worker.ctx.set_status_codes(vec![404]);
+        assert!(
+            worker.check_status_code(404),
+            "non-empty lists are unaffected"
+        );
+    }
+
+    #[test]
     fn check_status_codes_should_use_default_status_codes_if_200_to_300_if_no_status_codes_are_set()
     {
         let worker = Worker::new();
 
-        assert!(worker.check_status_code(200));
-        assert!(!worker.check_status_code(404));
+        assert!(worker.check_status_code(200));
+        assert!(!worker.check_status_code(404));
+    }
+
+    #[test]
+    fn check_status_codes_should_accept_any_2xx_in_addition_to_status_codes() {
+        let mut worker = Worker::new();
+        let req = Request::new(Method::GET, "https://google.com".to_string())
+            .with_status_codes(vec![200, 404])
+            .accept_any_2xx();
+        worker.ctx.update_from_request(req).unwrap();
+
+        assert!(worker.check_status_code(201));
+        assert!(worker.check_status_code(204));
+        assert!(worker.check_status_code(404));
+        assert!(!worker.check_status_code(500));
+
+        let req = Request::new(Method::GET, "https://google.com".to_string())
+            .with_status_codes(vec![200]);
+        worker.ctx.update_from_request(req).unwrap();
+
+        assert!(!worker.check_status_code(201));
+    }
+
+    #[test]
+    fn check_status_codes_should_accept_codes_in_a_range_or_the_list() {
+        let mut worker = Worker::new();
+        let req = Request::new(Method::GET, "https://google.com".to_string())
+            .with_status_ranges(vec![200..=299, 300..=399]);
+        worker.ctx.update_from_request(req).unwrap();
+
+        for (status, expected) in [(200, true), (302, true), (399, true), (404, false)] {
+            assert_eq!(worker.check_status_code(status), expected, "{}", status);
+        }
+
+        let req = Request::new(Method::GET, "https://google.com".to_string())
+            .with_status_codes(vec![404])
+            .with_status_ranges(vec![500..=503]);
+        worker.ctx.update_from_request(req).unwrap();
+
+        for (status, expected) in [(200, false), (404, true), (502, true), (504, false)] {
+            assert_eq!(worker.check_status_code(status), expected, "{}", status);
+        }
+    }
+
+    #[test]
+    fn check_status_codes_should_accept_exactly_the_2xx_range_by_default() {
+        let mut worker = Worker::new();
+
+        for (status, expected) in [(199, false), (200, true), (299, true), (300, false)] {
+            assert_eq!(worker.check_status_code(status), expected, "{}", status);
+        }
+
+        worker
+            .ctx
+            .client_settings_mut()
+            .set_redirects_as_success(true);
+        for (status, expected) in [
+            (199, false),
+            (200, true),
+            (300, true),
+            (399, true),
+            (400, false),
+        ] {
+            assert_eq!(worker.check_status_code(status), expected, "{}", status);
+        }
+
+        worker.ctx.set_status_codes(vec![200]);
+        assert!(!worker.check_status_code(301), "listed status codes win");
+    }
+
+    #[test]
+    fn it_should_skip_to_step() {
+        let mut worker = Worker::new();
+        worker.add_step(SkippableStep);
+
+        let step = worker.get_step(SKIPPABLE_STEP).unwrap();
+        let req = step.on_request();
+
+        assert_eq!(req.get_skip_to_step().unwrap(), ROBOTS_TXT);
+    }
+
+    #[tokio::test]
+    async fn concurrency_limit_should_cap_in_flight_requests() {
+        let server =
+            TestServer::start(|_| TestResponse::ok("ok").delay(Duration::from_millis(100))).await;
+
+        let mut worker = Worker::new().with_concurrency_limit(2);
+        worker.add_step(FetchStep::new(server.url("/")));
+
+        // forks share the limit of the worker they were forked from
+        let (mut a, mut b, mut c, mut d) =
+            (worker.fork(), worker.fork(), worker.fork(), worker.fork());
+        let (ra, rb, rc, rd) = tokio::join!(
+            a.try_step(FETCH_STEP),
+            b.try_step(FETCH_STEP),
+            c.try_step(FETCH_STEP),
+            d.try_step(FETCH_STEP),
+        );
+        assert!(ra.is_ok() && rb.is_ok() && rc.is_ok() && rd.is_ok());
+
+        assert_eq!(server.hits(), 4);
+        assert_eq!(server.max_in_flight(), 2);
+    }
+
+    #[tokio::test]
+    async fn per_host_concurrency_should_cap_in_flight_requests_to_a_host() {
+        let server =
+            TestServer::start(|_| TestResponse::ok("ok").delay(Duration::from_millis(100))).await;
+        let other =
+            TestServer::start(|_| TestResponse::ok("ok").delay(Duration::from_millis(100))).await;
+
+        let mut worker = Worker::new();
+        worker.set_per_host_concurrency(2);
+        worker.add_step(FetchStep::named(
+            "Local",
+            Request::new(Method::GET, server.url("/")),
+        ));
+        // another host name for the same machine, with its own limit
+        let other_url = other.url("/").replace("127.0.0.1", "localhost");
+        worker.add_step(FetchStep::named(
+            "Other",
+            Request::new(Method::GET, other_url),
+        ));
+
+        let (queue_tx, queue_rx) = tokio::sync::mpsc::channel(12);
+        for _ in 0..6 {
+            queue_tx.send("Local".to_string()).await.unwrap();
+            queue_tx.send("Other".to_string()).await.unwrap();
+        }
+        drop(queue_tx);
+        let (out_tx, mut out_rx) = tokio::sync::mpsc::channel(12);
+
+        worker.run_queue(queue_rx, out_tx, 6).await;
+
+        let mut outcomes = 0;
+        while let Some(outcome) = out_rx.recv().await {
+            assert!(outcome.is_success());
+            outcomes += 1;
+        }
+        assert_eq!(outcomes, 12);
+        assert_eq!(server.hits(), 6);
+        assert_eq!(server.max_in_flight(), 2);
+        assert_eq!(other.max_in_flight(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_when_content_type_is_unexpected() {
+        let server = TestServer::start(|_| {
+            TestResponse::ok("<html></html>").header("Content-Type", "text/html; charset=utf-8")
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::with_request(
+            Request::new(Method::GET, server.url("/api")).expect_content_type("application/json"),
+        ));
+
+        let err = worker.try_step(FETCH_STEP).await.unwrap_err();
+        match err.downcast_ref::<StepError>() {
+            Some(StepError::UnexpectedContentType { expected, got }) => {
+                assert_eq!(expected, "application/json");
+                assert_eq!(got, "text/html; charset=utf-8");
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_accept_expected_content_type_with_parameters() {
+        let server = TestServer::start(|_| {
+            TestResponse::ok("{}").header("Content-Type", "application/json; charset=utf-8")
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::with_request(
+            Request::new(Method::GET, server.url("/api")).expect_content_type("application/json"),
+        ));
+
+        assert!(worker.try_step(FETCH_STEP).await.is_ok());
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn it_should_fail_when_the_json_kind_is_unexpected() {
+        use crate::JsonKind;
+
+        let server = TestServer::start(|req| match req.target.as_str() {
+            "/list" => TestResponse::ok("[]"),
+            "/html" => TestResponse::ok("<html></html>"),
+            _ => TestResponse::ok(r#"{"id": 1}"#),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        for path in ["/list", "/html", "/item"] {
+            worker.add_step(FetchStep::named(
+                path,
+                Request::new(Method::GET, server.url(path)).expect_json_kind(JsonKind::Object),
+            ));
+        }
+
+        let err = worker.try_step("/list").await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<StepError>(),
+            Some(StepError::UnexpectedJsonKind {
+                expected: JsonKind::Object,
+                got: Some(JsonKind::Array),
+            })
+        ));
+        assert_eq!(err.to_string(), "Unexpected JSON array. Expected: object");
+
+        let err = worker.try_step("/html").await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<StepError>(),
+            Some(StepError::UnexpectedJsonKind { got: None, .. })
+        ));
+
+        worker.try_step("/item").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_when_the_body_is_smaller_than_the_minimum() {
+        let server = TestServer::ok("blank").await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::with_request(
+            Request::new(Method::GET, server.url("/")).expect_min_body_size(100),
+        ));
+
+        let err = worker.try_step(FETCH_STEP).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<StepError>(),
+            Some(StepError::BodyTooSmall { got: 5, min: 100 })
+        ));
+
+        worker.add_step(FetchStep::with_request(
+            Request::new(Method::GET, server.url("/")).expect_min_body_size(5),
+        ));
+        worker.try_step(FETCH_STEP).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn before_request_should_add_a_header_from_context_state() {
+        let server = TestServer::start(|req| match req.target.as_str() {
+            "/token" => TestResponse::ok("abc123"),
+            _ => TestResponse::ok("secret"),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::new(server.url("/token")));
+        worker.add_step(AuthedStep {
+            url: server.url("/private"),
+        });
+
+        worker.try_step(FETCH_STEP).await.unwrap();
+        worker.try_step(AUTHED_STEP).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("Authorization"), None);
+        assert_eq!(requests[1].header("Authorization"), Some("Bearer abc123"));
+    }
+
+    #[tokio::test]
+    async fn stats_should_reflect_recorded_runs() {
+        let server = TestServer::start(|req| match req.target.as_str() {
+            "/missing" => TestResponse::status(404),
+            _ => TestResponse::ok("fast").delay(Duration::from_millis(50)),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::named(
+            "Fast",
+            Request::new(Method::GET, server.url("/fast")),
+        ));
+        worker.add_step(FetchStep::named(
+            "Missing",
+            Request::new(Method::GET, server.url("/missing")),
+        ));
+
+        for _ in 0..3 {
+            worker.try_step("Fast").await.unwrap();
+        }
+        assert!(worker.try_step("Missing").await.is_err());
+
+        let stats = worker.stats();
+        assert_eq!(stats.runs, 4);
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.steps["Fast"].runs, 3);
+        assert_eq!(stats.steps["Missing"].errors, 1);
+        assert!(
+            stats.steps["Fast"].latency.p50 >= 50,
+            "a latency is recorded per request sent"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_should_stop_decompressing_past_the_limit() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&vec![0u8; 1024 * 1024]).unwrap();
+        let bomb = encoder.finish().unwrap();
+        assert!(bomb.len() < 16 * 1024);
+
+        let server = TestServer::start(move |_| {
+            TestResponse::ok(bomb.clone()).header("Content-Encoding", "gzip")
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker
+            .ctx
+            .client_settings_mut()
+            .set_max_decompressed_bytes(Some(64 * 1024));
+        worker.add_step(FetchStep::new(server.url("/")));
+
+        let err = worker.try_step(FETCH_STEP).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<StepError>(),
+            Some(StepError::DecompressionLimitExceeded(65536))
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_should_decompress_bodies_within_the_limit() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(b"hello world").unwrap();
+        let body = encoder.finish().unwrap();
+
+        let server = TestServer::start(move |_| {
+            TestResponse::ok(body.clone()).header("Content-Encoding", "gzip")
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker
+            .ctx
+            .client_settings_mut()
+            .set_max_decompressed_bytes(Some(1024));
+        worker.add_step(FetchStep::new(server.url("/")));
+
+        worker.try_step(FETCH_STEP).await.unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), "hello world");
+    }
+
+    #[cfg(feature = "zstd")]
+    #[tokio::test]
+    async fn it_should_decompress_zstd_bodies() {
+        let text = "hello zstd ".repeat(100);
+        let compressed = zstd::encode_all(text.as_bytes(), 3).unwrap();
+
+        let server = TestServer::start(move |req| match req.target.as_str() {
+            "/encoded" => TestResponse::ok(compressed.clone()).header("Content-Encoding", "zstd"),
+            _ => TestResponse::ok(compressed.clone()),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.ctx.client_settings_mut().set_zstd(true);
+        worker.add_step(FetchStep::named(
+            "Encoded",
+            Request::new(Method::GET, server.url("/encoded")),
+        ));
+        worker.add_step(FetchStep::named(
+            "Sniffed",
+            Request::new(Method::GET, server.url("/sniffed")),
+        ));
+
+        worker.try_step("Encoded").await.unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), text);
+        assert_eq!(
+            server.requests()[0].header("Accept-Encoding"),
+            Some("gzip, zstd")
+        );
+
+        worker.try_step("Sniffed").await.unwrap();
+        assert_eq!(
+            worker.ctx.body_text().unwrap(),
+            text,
+            "found by magic bytes"
+        );
+
+        worker
+            .ctx
+            .client_settings_mut()
+            .set_max_decompressed_bytes(Some(64));
+        let err = worker.try_step("Encoded").await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<StepError>(),
+            Some(StepError::DecompressionLimitExceeded(64))
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_should_report_the_compression_ratio() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let text = "hello world ".repeat(1000);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(text.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let gzipped_len = gzipped.len() as u64;
+
+        let server = TestServer::start(move |req| match req.target.as_str() {
+            "/gzip" => TestResponse::ok(gzipped.clone()).header("Content-Encoding", "gzip"),
+            _ => TestResponse::ok("plain"),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::named(
+            "Gzip",
+            Request::new(Method::GET, server.url("/gzip")),
+        ));
+        worker.add_step(FetchStep::named(
+            "Plain",
+            Request::new(Method::GET, server.url("/plain")),
+        ));
+
+        worker.try_step("Gzip").await.unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), text);
+        assert_eq!(worker.ctx.transfer_size(), Some(gzipped_len));
+        assert!(worker.ctx.compression_ratio().unwrap() > 1.0);
+        assert_eq!(server.requests()[0].header("Accept-Encoding"), Some("gzip"));
+
+        worker.try_step("Plain").await.unwrap();
+        assert_eq!(worker.ctx.compression_ratio(), Some(1.0));
+    }
+
+    #[tokio::test]
+    async fn it_should_record_the_http_version_of_the_response() {
+        let server = TestServer::ok("ok").await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::new(server.url("/")));
+        assert_eq!(worker.ctx.response_version(), None);
+
+        worker.try_step(FETCH_STEP).await.unwrap();
+
+        assert_eq!(worker.ctx.http_version(), Some(reqwest::Version::HTTP_11));
+        assert_eq!(worker.ctx.response_version().as_deref(), Some("HTTP/1.1"));
+    }
+
+    #[tokio::test]
+    async fn it_should_send_requests_to_the_normalized_url() {
+        let server = TestServer::ok("ok").await;
+
+        let mut worker = Worker::new();
+        worker.set_url_normalizer(DefaultUrlNormalizer);
+        worker.add_step(FetchStep::new(server.url("/items?b=2&a=1")));
+        let mut fork = worker.fork();
+
+        worker.try_step(FETCH_STEP).await.unwrap();
+        fork.try_step(FETCH_STEP).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].target, "/items?a=1&b=2");
+        assert_eq!(requests[1].target, "/items?a=1&b=2");
+    }
+
+    #[cfg(feature = "wire-log")]
+    #[tokio::test]
+    async fn it_should_log_the_request_and_response_bytes() {
+        use crate::request::MimicBody;
+        use crate::WireEvent;
+
+        let server = TestServer::start(|_| TestResponse::ok("pong").header("X-Reply", "yes")).await;
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let logged = events.clone();
+
+        let mut worker = Worker::new();
+        worker.set_wire_logger(Arc::new(move |event| logged.lock().unwrap().push(event)));
+        worker.add_step(FetchStep::with_request(
+            Request::new(Method::POST, server.url("/ping"))
+                .with_body(MimicBody::from_text("ping".to_string())),
+        ));
+
+        worker.try_step(FETCH_STEP).await.unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        let WireEvent::RequestSent(request) = &events[0] else {
+            panic!("expected the request first, got {:?}", events[0]);
+        };
+        let request = String::from_utf8_lossy(request);
+        assert!(
+            request.starts_with("POST /ping HTTP/1.1\r\n"),
+            "{}",
+            request
+        );
+        assert!(request.ends_with("\r\n\r\nping"), "{}", request);
+
+        let WireEvent::ResponseReceived(response) = &events[1] else {
+            panic!("expected the response second, got {:?}", events[1]);
+        };
+        let response = String::from_utf8_lossy(response);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("x-reply: yes\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\npong"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn it_should_send_the_exact_accept_encoding() {
+        let server = TestServer::ok("ok").await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::named(
+            "Brotli",
+            Request::new(Method::GET, server.url("/")).with_accept_encoding("br"),
+        ));
+        worker.add_step(FetchStep::named(
+            "Identity",
+            Request::new(Method::GET, server.url("/")).with_accept_encoding(""),
+        ));
+
+        worker.try_step("Brotli").await.unwrap();
+        worker.try_step("Identity").await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("Accept-Encoding"), Some("br"));
+        assert_eq!(requests[1].header("Accept-Encoding"), None);
+    }
+
+    #[tokio::test]
+    async fn run_should_report_every_step_of_the_pipeline() {
+        let server = TestServer::start(|req| match req.target.as_str() {
+            "/missing" => TestResponse::status(404),
+            _ => TestResponse::ok("ok"),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(
+            FetchStep::named("Home", Request::new(Method::GET, server.url("/"))).then("Login"),
+        );
+        worker.add_step(
+            FetchStep::named("Login", Request::new(Method::GET, server.url("/login")))
+                .then("Missing"),
+        );
+        worker.add_step(
+            FetchStep::named("Missing", Request::new(Method::GET, server.url("/missing")))
+                .then("Home"),
+        );
+
+        let report = worker.run("Home").await;
+
+        assert_eq!(report.steps_run, 3);
+        assert_eq!(report.successes, 2);
+        assert_eq!(report.failures, 1);
+        assert!(report.total_elapsed >= report.per_step[0].elapsed);
+
+        let names: Vec<&str> = report.per_step.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names, vec!["Home", "Login", "Missing"]);
+
+        let missing = &report.per_step[2];
+        assert_eq!(missing.status, Some(404));
+        assert!(matches!(
+            missing.error,
+            Some(StepError::StatusCodeNotFound(404, _))
+        ));
+        assert_eq!(report.per_step[0].status, Some(200));
+        assert!(report.per_step[0].error.is_none());
+    }
+
+    #[tokio::test]
+    async fn run_should_report_unknown_steps_as_failures() {
+        let mut worker = Worker::new();
+        let report = worker.run("Nope").await;
+
+        assert_eq!(report.steps_run, 1);
+        assert_eq!(report.failures, 1);
+        assert!(matches!(
+            report.per_step[0].error,
+            Some(StepError::StepNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn check_content_length_should_detect_a_mismatched_length() {
+        let mut worker = Worker::new();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, "100".parse().unwrap());
+        worker.ctx.set_response_headers(headers);
+
+        assert!(worker.check_content_length(100).is_ok());
+        assert!(
+            worker.check_content_length(5).is_ok(),
+            "mismatches only warn by default"
+        );
+
+        let (handler, seen) = recording_error_handler();
+        worker.set_default_error_handler(handler);
+        worker.ctx.set_current_step("Download".to_string());
+        assert!(worker.check_content_length(5).is_ok());
+        assert_eq!(seen.lock().unwrap().len(), 1);
+        assert!(seen.lock().unwrap()[0].starts_with("Download: "));
+
+        worker
+            .ctx
+            .client_settings_mut()
+            .set_content_length_check(ContentLengthCheck::Ignore);
+        assert!(worker.check_content_length(5).is_ok());
+        assert_eq!(
+            seen.lock().unwrap().len(),
+            1,
+            "Ignore opts out of the check"
+        );
+
+        worker
+            .ctx
+            .client_settings_mut()
+            .set_content_length_check(ContentLengthCheck::Error);
+        assert!(matches!(
+            worker.check_content_length(5),
+            Err(StepError::ContentLengthMismatch {
+                expected: 100,
+                got: 5
+            })
+        ));
+    }
+
+    #[test]
+    fn check_content_length_should_ignore_bodiless_responses() {
+        let mut worker = Worker::new();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, "100".parse().unwrap());
+        worker.ctx.set_response_headers(headers);
+        worker.ctx.set_status_code(304);
+        worker
+            .ctx
+            .client_settings_mut()
+            .set_content_length_check(ContentLengthCheck::Error);
+
+        assert!(worker.check_content_length(0).is_ok());
+    }
+
+    #[tokio::test]
+    async fn it_should_run_queued_steps_with_bounded_concurrency() {
+        let server =
+            TestServer::start(|_| TestResponse::ok("ok").delay(Duration::from_millis(50))).await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::named(
+            "A",
+            Request::new(Method::GET, server.url("/a")),
+        ));
+        worker.add_step(FetchStep::named(
+            "B",
+            Request::new(Method::GET, server.url("/b")),
+        ));
+
+        let (queue_tx, queue_rx) = tokio::sync::mpsc::channel(2);
+        let (out_tx, mut out_rx) = tokio::sync::mpsc::channel(1);
+
+        let producer = tokio::spawn(async move {
+            for name in ["A", "B", "A", "Missing", "B", "A"] {
+                queue_tx.send(name.to_string()).await.unwrap();
+            }
+        });
+        let collector = async {
+            let mut outcomes = Vec::new();
+            while let Some(outcome) = out_rx.recv().await {
+                outcomes.push(outcome);
+            }
+            outcomes
+        };
+
+        let (_, outcomes) = tokio::join!(worker.run_queue(queue_rx, out_tx, 2), collector);
+        producer.await.unwrap();
+
+        assert_eq!(outcomes.len(), 6);
+        assert_eq!(outcomes.iter().filter(|o| o.is_success()).count(), 5);
+        assert!(matches!(
+            outcomes.iter().find(|o| o.name == "Missing").unwrap().error,
+            Some(StepError::StepNotFound(_))
+        ));
+        assert_eq!(server.hits(), 5);
+        assert_eq!(server.max_in_flight(), 2);
+
+        let stats = worker.stats();
+        assert_eq!(stats.runs, 6);
+        assert_eq!(stats.steps["A"].runs, 3);
+    }
+
+    #[tokio::test]
+    async fn it_should_replay_the_last_request_without_hooks() {
+        let server = TestServer::start(|req| match req.header("Cookie") {
+            Some(_) => TestResponse::ok("payload"),
+            None => TestResponse::ok("payload").header("Set-Cookie", "session=abc"),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::new(server.url("/flaky")).then("Unreachable"));
+        assert!(worker.ctx.replay().await.is_err(), "nothing to replay yet");
+
+        worker.try_step(FETCH_STEP).await.unwrap();
+        worker.ctx.clear_next_step();
+
+        let replayed = worker.ctx.replay().await.unwrap();
+
+        assert_eq!(replayed, worker.ctx.body_bytes().unwrap());
+        assert_eq!(worker.ctx.get_next_step(), None, "hooks should not run");
+        assert_eq!(server.hits(), 2);
+        let requests = server.requests();
+        assert_eq!(requests[1].target, "/flaky");
+        assert_eq!(requests[1].header("Cookie"), Some("session=abc"));
+    }
+
+    #[tokio::test]
+    async fn adaptive_timeout_should_grow_back_when_latency_rises() {
+        let slow = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let server_slow = slow.clone();
+        let server = TestServer::start(move |_| {
+            match server_slow.load(std::sync::atomic::Ordering::SeqCst) {
+                true => TestResponse::ok("slow").delay(Duration::from_millis(150)),
+                false => TestResponse::ok("fast"),
+            }
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.set_adaptive_timeout(Some(
+            AdaptiveTimeout::new(2.0, Duration::from_millis(10), Duration::from_secs(1))
+                .with_min_samples(5),
+        ));
+        worker.add_step(FetchStep::new(server.url("/")));
+        for _ in 0..5 {
+            worker.try_step(FETCH_STEP).await.unwrap();
+        }
+
+        slow.store(true, std::sync::atomic::Ordering::SeqCst);
+        let mut timeouts = 0;
+        while worker.try_step(FETCH_STEP).await.is_err() {
+            timeouts += 1;
+            assert!(timeouts < 10, "the timeout should grow back");
+        }
+        assert!(timeouts > 0, "the shrunk timeout should have fired first");
+        assert_eq!(worker.ctx.body_text().unwrap(), "slow");
+    }
+
+    #[tokio::test]
+    async fn replay_should_respect_the_concurrency_limit() {
+        let server =
+            TestServer::start(|_| TestResponse::ok("ok").delay(Duration::from_millis(100))).await;
+
+        let mut worker = Worker::new().with_concurrency_limit(1);
+        worker.add_step(FetchStep::new(server.url("/")));
+        worker.try_step(FETCH_STEP).await.unwrap();
+
+        let mut fork = worker.fork();
+        let (replayed, forked) = tokio::join!(worker.ctx.replay(), fork.try_step(FETCH_STEP));
+        replayed.unwrap();
+        forked.unwrap();
+
+        assert_eq!(server.hits(), 3);
+        assert_eq!(server.max_in_flight(), 1);
+    }
+
+    /// A step recording which failure hooks ran.
+    struct RetryStep {
+        url: String,
+        retries: u32,
+        calls: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    const RETRY_STEP: &str = "RetryStep";
+
+    #[async_trait]
+    impl Stepable for RetryStep {
+        fn name(&self) -> String {
+            String::from(RETRY_STEP)
+        }
+
+        fn on_request(&self) -> Request {
+            Request::new(Method::GET, self.url.clone()).with_retries(self.retries)
+        }
+
+        fn on_success(&self, _ctx: &mut Context) {
+            self.calls.lock().unwrap().push("success".to_string());
+        }
+
+        fn on_error(&self, _ctx: &mut Context, err: StepError) {
+            self.calls.lock().unwrap().push(format!("error: {}", err));
+        }
+
+        fn on_timeout(&self, _ctx: &mut Context) {
+            self.calls.lock().unwrap().push("timeout".to_string());
+        }
+
+        fn on_retry_exhausted(&self, _ctx: &mut Context, attempts: u32, last: StepError) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("exhausted after {}: {}", attempts, last));
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_call_on_retry_exhausted_once_retries_run_out() {
+        let server = TestServer::start(|_| TestResponse::status(500)).await;
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let mut worker = Worker::new();
+        worker.add_step(RetryStep {
+            url: server.url("/"),
+            retries: 2,
+            calls: calls.clone(),
+        });
+
+        let err = worker.try_step(RETRY_STEP).await.unwrap_err();
+
+        assert!(err.to_string().contains("500"));
+        assert_eq!(server.hits(), 3);
+        let calls = calls.lock().unwrap();
+        assert_eq!(
+            calls.len(),
+            1,
+            "intermediate failures shouldn't reach the hooks"
+        );
+        assert!(calls[0].starts_with("exhausted after 3: "));
+        assert_eq!(worker.stats().runs, 1);
+    }
+
+    #[tokio::test]
+    async fn it_should_succeed_when_a_retry_succeeds() {
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let server_hits = hits.clone();
+        let server = TestServer::start(move |_| {
+            match server_hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => TestResponse::status(503),
+                _ => TestResponse::ok("recovered"),
+            }
+        })
+        .await;
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let mut worker = Worker::new();
+        worker.add_step(RetryStep {
+            url: server.url("/"),
+            retries: 3,
+            calls: calls.clone(),
+        });
+
+        worker.try_step(RETRY_STEP).await.unwrap();
+
+        assert_eq!(server.hits(), 2);
+        assert_eq!(*calls.lock().unwrap(), vec!["success".to_string()]);
+        assert_eq!(worker.ctx.body_text().unwrap(), "recovered");
+    }
+
+    #[tokio::test]
+    async fn it_should_retry_on_a_listed_status_after_its_retry_after() {
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let server_hits = hits.clone();
+        let server = TestServer::start(move |_| {
+            match server_hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 | 1 => TestResponse::status(503).header("Retry-After", "0"),
+                _ => TestResponse::ok("recovered"),
+            }
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        // 503 is accepted by the step but retried anyway, without waiting for the backoff
+        worker.add_step(FetchStep::with_request(
+            Request::new(Method::GET, server.url("/"))
+                .with_status_codes(vec![200, 503])
+                .with_retries(2)
+                .with_retry_backoff(Duration::from_secs(30))
+                .with_retry_on_status(vec![503]),
+        ));
+
+        worker.try_step(FETCH_STEP).await.unwrap();
+
+        assert_eq!(server.hits(), 3);
+        assert_eq!(
+            worker.retry_delay(Some(Duration::ZERO)),
+            Some(Duration::ZERO),
+            "the Retry-After wins over the backoff"
+        );
+        assert_eq!(worker.ctx.body_text().unwrap(), "recovered");
+    }
+
+    #[test]
+    fn it_should_cap_the_delay_a_retry_after_asks_for() {
+        let mut worker = Worker::new();
+        worker
+            .ctx
+            .client_settings_mut()
+            .set_max_retry_after(Duration::from_secs(60));
+        let req = Request::new(Method::GET, "https://google.com".to_string())
+            .with_retry_backoff(Duration::from_secs(2));
+        worker.ctx.update_from_request(req).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "86400".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(86400)));
+        assert_eq!(
+            worker.retry_delay(retry_after(&headers)),
+            Some(Duration::from_secs(60))
+        );
+
+        let far_future = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(86400));
+        headers.insert(RETRY_AFTER, far_future.parse().unwrap());
+        assert_eq!(
+            worker.retry_delay(retry_after(&headers)),
+            Some(Duration::from_secs(60))
+        );
+
+        headers.insert(RETRY_AFTER, "5".parse().unwrap());
+        assert_eq!(
+            worker.retry_delay(retry_after(&headers)),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            worker.retry_delay(None),
+            Some(Duration::from_secs(2)),
+            "the backoff without a Retry-After"
+        );
+    }
+
+    #[test]
+    fn it_should_pause_until_the_rate_limit_resets() {
+        let mut worker = Worker::new();
+        worker
+            .ctx
+            .client_settings_mut()
+            .set_max_retry_after(Duration::from_secs(60));
+        let rate_limit = |remaining: &str, reset: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("X-RateLimit-Remaining", remaining.parse().unwrap());
+            headers.insert("X-RateLimit-Reset", reset.parse().unwrap());
+            headers
+        };
+
+        worker.ctx.set_response_headers(rate_limit("0", "30"));
+        assert_eq!(worker.rate_limit_pause(), Some(Duration::from_secs(30)));
+
+        worker.ctx.set_response_headers(rate_limit("0", "86400"));
+        assert_eq!(
+            worker.rate_limit_pause(),
+            Some(Duration::from_secs(60)),
+            "the pause is capped"
+        );
+
+        let unix_time = |offset: u64| {
+            let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
+            (now.unwrap().as_secs() + offset).to_string()
+        };
+        worker
+            .ctx
+            .set_response_headers(rate_limit("0", &unix_time(86400)));
+        assert_eq!(worker.rate_limit_pause(), Some(Duration::from_secs(60)));
+        worker
+            .ctx
+            .set_response_headers(rate_limit("0", "1000000000"));
+        assert_eq!(
+            worker.rate_limit_pause(),
+            Some(Duration::ZERO),
+            "a reset in the past"
+        );
+
+        worker.ctx.set_response_headers(rate_limit("1", "30"));
+        assert_eq!(worker.rate_limit_pause(), None);
+    }
+
+    #[tokio::test]
+    async fn it_should_only_pause_on_rate_limits_when_enabled() {
+        let server = TestServer::start(|_| {
+            TestResponse::ok("ok")
+                .header("X-RateLimit-Remaining", "0")
+                .header("X-RateLimit-Reset", "3600")
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::new(server.url("/")));
+        worker.try_step(FETCH_STEP).await.unwrap();
+        worker.try_step(FETCH_STEP).await.unwrap();
+        assert_eq!(server.hits(), 2, "pausing is opt-in");
+
+        worker
+            .ctx
+            .client_settings_mut()
+            .set_max_retry_after(Duration::ZERO);
+        worker.pause_on_rate_limit(true);
+        worker.try_step(FETCH_STEP).await.unwrap();
+        worker.try_step(FETCH_STEP).await.unwrap();
+        assert_eq!(server.hits(), 4, "the capped pause is over right away");
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_once_status_retries_are_used_up() {
+        let server = TestServer::start(|_| TestResponse::status(503)).await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::with_request(
+            Request::new(Method::GET, server.url("/"))
+                .with_retries(1)
+                .with_retry_on_status(vec![503]),
+        ));
+
+        let err = worker.try_step(FETCH_STEP).await.unwrap_err();
+
+        assert!(err.to_string().contains("503"));
+        assert_eq!(server.hits(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_should_give_later_attempts_more_time() {
+        let server =
+            TestServer::start(|_| TestResponse::ok("slow").delay(Duration::from_millis(500))).await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::named(
+            "Grows",
+            Request::new(Method::GET, server.url("/"))
+                .with_timeout(Duration::from_millis(300))
+                .with_retries(2)
+                .with_timeout_growth(Duration::from_secs(2)),
+        ));
+        worker.add_step(FetchStep::named(
+            "Capped",
+            Request::new(Method::GET, server.url("/"))
+                .with_timeout(Duration::from_millis(300))
+                .with_retries(2)
+                .with_timeout_growth(Duration::from_millis(400)),
+        ));
+
+        // the first attempt times out, the second one gets 600ms
+        worker.try_step("Grows").await.unwrap();
+        assert_eq!(server.hits(), 2);
+        assert_eq!(
+            worker.ctx.get_request().timeout(),
+            Some(Duration::from_millis(600))
+        );
+
+        // every attempt is capped at 400ms
+        assert!(worker.try_step("Capped").await.is_err());
+        assert_eq!(server.hits(), 5);
+        assert_eq!(
+            worker.ctx.get_request().timeout(),
+            Some(Duration::from_millis(400))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_should_call_on_error_without_retries() {
+        let server = TestServer::start(|_| TestResponse::status(500)).await;
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let mut worker = Worker::new();
+        worker.add_step(RetryStep {
+            url: server.url("/"),
+            retries: 0,
+            calls: calls.clone(),
+        });
+
+        assert!(worker.try_step(RETRY_STEP).await.is_err());
+        assert_eq!(server.hits(), 1);
+        assert!(calls.lock().unwrap()[0].starts_with("error: "));
+    }
+
+    #[tokio::test]
+    async fn it_should_track_body_sizes() {
+        let server = TestServer::start(|req| {
+            let size: usize = req.target.trim_start_matches('/').parse().unwrap();
+            TestResponse::ok("x".repeat(size))
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        assert_eq!(worker.body_size_stats(), crate::SizeStats::default());
+
+        for size in [100, 300, 800] {
+            worker.add_step(FetchStep::new(server.url(&format!("/{}", size))));
+            worker.try_step(FETCH_STEP).await.unwrap();
+        }
+
+        let sizes = worker.body_size_stats();
+        assert_eq!(sizes.max, 800);
+        assert_eq!(sizes.mean, 400);
+        assert_eq!(sizes.p95, 800);
+    }
+
+    /// A step relying on the default error handling.
+    struct PlainStep {
+        url: String,
+    }
+
+    const PLAIN_STEP: &str = "PlainStep";
+
+    #[async_trait]
+    impl Stepable for PlainStep {
+        fn name(&self) -> String {
+            String::from(PLAIN_STEP)
+        }
+
+        fn on_request(&self) -> Request {
+            Request::new(Method::GET, self.url.clone())
+        }
+
+        fn on_success(&self, _ctx: &mut Context) {}
+
+        fn on_timeout(&self, _ctx: &mut Context) {}
+    }
+
+    fn recording_error_handler() -> (crate::ErrorHandler, Arc<std::sync::Mutex<Vec<String>>>) {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let handler_seen = seen.clone();
+        let handler: crate::ErrorHandler = Arc::new(move |step: &str, err: &StepError| {
+            handler_seen
+                .lock()
+                .unwrap()
+                .push(format!("{}: {}", step, err));
+        });
+        (handler, seen)
+    }
+
+    #[tokio::test]
+    async fn it_should_fall_back_to_the_default_error_handler() {
+        let server = TestServer::start(|_| TestResponse::status(404)).await;
+        let (handler, seen) = recording_error_handler();
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let mut worker = Worker::new();
+        worker.set_default_error_handler(handler);
+        worker.add_step(PlainStep {
+            url: server.url("/"),
+        });
+        worker.add_step(RetryStep {
+            url: server.url("/"),
+            retries: 0,
+            calls: calls.clone(),
+        });
+
+        assert!(worker.try_step(PLAIN_STEP).await.is_err());
+        assert!(worker.try_step(RETRY_STEP).await.is_err());
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(
+            seen.len(),
+            1,
+            "steps with their own on_error aren't passed on"
+        );
+        assert!(seen[0].starts_with("PlainStep: "));
+        assert!(seen[0].contains("404"));
+        assert_eq!(calls.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_should_always_call_the_default_error_handler_when_configured() {
+        let server = TestServer::start(|_| TestResponse::status(500)).await;
+        let (handler, seen) = recording_error_handler();
+
+        let mut worker = Worker::new();
+        worker.set_default_error_handler(handler);
+        worker.always_use_default_error_handler(true);
+        worker.add_step(PlainStep {
+            url: server.url("/"),
+        });
+        worker.add_step(RetryStep {
+            url: server.url("/"),
+            retries: 0,
+            calls: Arc::new(std::sync::Mutex::new(Vec::new())),
+        });
+
+        assert!(worker.try_step(PLAIN_STEP).await.is_err());
+        assert!(worker.try_step(RETRY_STEP).await.is_err());
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert!(seen[0].starts_with("PlainStep: "));
+        assert!(seen[1].starts_with("RetryStep: "));
+    }
+
+    #[tokio::test]
+    async fn it_should_always_call_the_default_error_handler_on_timeouts_when_configured() {
+        let server =
+            TestServer::start(|_| TestResponse::ok("late").delay(Duration::from_millis(500))).await;
+        let (handler, seen) = recording_error_handler();
+
+        let mut worker = Worker::new();
+        worker.set_default_error_handler(handler);
+        worker.always_use_default_error_handler(true);
+        worker.add_step(FetchStep::named(
+            "Slow",
+            Request::new(Method::GET, server.url("/")).with_timeout(Duration::from_millis(50)),
+        ));
+
+        assert!(worker.try_step("Slow").await.is_err());
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert!(seen[0].starts_with("Slow: "));
+        assert!(matches!(
+            worker.ctx.last_error(),
+            Some(StepError::Timeout { .. })
+        ));
+    }
+
+    /// A step polling with the ETag of the previous response.
+    struct PollStep {
+        url: String,
+    }
+
+    const POLL_STEP: &str = "PollStep";
+
+    #[async_trait]
+    impl Stepable for PollStep {
+        fn name(&self) -> String {
+            String::from(POLL_STEP)
+        }
+
+        fn on_request(&self) -> Request {
+            Request::new(Method::GET, self.url.clone()).expect_content_type("application/json")
+        }
+
+        fn before_request(&self, ctx: &Context, req: Request) -> Request {
+            match ctx.etag() {
+                Some(etag) => req.with_if_none_match(etag),
+                None => req,
+            }
+        }
+
+        fn on_success(&self, _ctx: &mut Context) {}
+
+        fn on_timeout(&self, _ctx: &mut Context) {}
+    }
+
+    #[tokio::test]
+    async fn it_should_treat_a_304_as_not_modified() {
+        let server = TestServer::start(|req| match req.header("If-None-Match") {
+            Some("\"v1\"") => TestResponse::status(304).header("ETag", "\"v1\""),
+            _ => TestResponse::ok("[1, 2]")
+                .header("Content-Type", "application/json")
+                .header("ETag", "\"v1\""),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(PollStep {
+            url: server.url("/feed"),
+        });
+
+        worker.try_step(POLL_STEP).await.unwrap();
+        assert!(!worker.ctx.not_modified());
+        assert_eq!(worker.ctx.etag().as_deref(), Some("\"v1\""));
+
+        worker.try_step(POLL_STEP).await.unwrap();
+        assert!(worker.ctx.not_modified());
+        assert_eq!(worker.ctx.get_status_code(), Some(304));
+        assert_eq!(server.requests()[1].header("If-None-Match"), Some("\"v1\""));
+
+        // a 304 to an unconditional request is still an error
+        let server = TestServer::start(|_| TestResponse::status(304)).await;
+        let mut unconditional = Worker::new();
+        unconditional.add_step(FetchStep::new(server.url("/feed")));
+        assert!(unconditional.try_step(FETCH_STEP).await.is_err());
+        assert!(!unconditional.ctx.not_modified());
+    }
+
+    #[tokio::test]
+    async fn it_should_run_forks_with_isolated_contexts() {
+        let server = TestServer::start(|req| match req.header("Cookie") {
+            Some(cookie) => TestResponse::ok(format!("seen {}", cookie)),
+            None if req.target == "/login" => {
+                TestResponse::ok("welcome").header("Set-Cookie", "session=abc")
+            }
+            None => TestResponse::ok("anonymous"),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::named(
+            "Login",
+            Request::new(Method::GET, server.url("/login")),
+        ));
+        worker.add_step(FetchStep::named(
+            "Home",
+            Request::new(Method::GET, server.url("/home")),
+        ));
+
+        let mut first = worker.fork();
+        let mut second = worker.fork();
+        assert!(first.has_step("Login") && second.has_step("Home"));
+
+        let (a, b) = tokio::join!(first.try_step("Login"), second.try_step("Home"));
+        a.unwrap();
+        b.unwrap();
+        first.try_step("Home").await.unwrap();
+        second.try_step("Home").await.unwrap();
+
+        assert_eq!(first.ctx.body_text().unwrap(), "seen session=abc");
+        assert_eq!(second.ctx.body_text().unwrap(), "anonymous");
+        assert_eq!(first.stats().runs, 2);
+        assert_eq!(worker.stats().runs, 0);
+    }
+
+    #[tokio::test]
+    async fn forks_should_not_inherit_an_injected_client() {
+        let server = TestServer::ok("ok").await;
+
+        let cookie_store = crate::http_requester::new_cookie_store();
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert("X-Custom-Client", "yes".parse().unwrap());
+        let client = reqwest::Client::builder()
+            .cookie_provider(cookie_store.clone())
+            .default_headers(default_headers)
+            .build()
+            .unwrap();
+
+        let mut worker = Worker::new();
+        worker
+            .ctx
+            .set_http_requester(crate::HttpRequester::from_client(client, cookie_store));
+        worker.add_step(FetchStep::new(server.url("/")));
+
+        worker.fork().try_step(FETCH_STEP).await.unwrap();
+
+        assert_eq!(
+            server.requests()[0].header("X-Custom-Client"),
+            None,
+            "the fork should send with a client built from the settings"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_should_report_the_phase_of_a_timeout() {
+        let server = TestServer::start(|req| match req.target.as_str() {
+            "/slow" => TestResponse::ok("late").delay(Duration::from_millis(500)),
+            _ => TestResponse::ok("").chunked(vec![b"x".to_vec(); 200]),
+        })
+        .await;
+
+        for (path, expected) in [
+            ("/slow", TimeoutPhase::Overall),
+            ("/trickle", TimeoutPhase::Read),
+        ] {
+            let mut worker = Worker::new();
+            worker.add_step(FetchStep::with_request(
+                Request::new(Method::GET, server.url(path))
+                    .with_timeout(Duration::from_millis(150)),
+            ));
+
+            let err = worker.try_step(FETCH_STEP).await.unwrap_err();
+            match err.downcast_ref::<StepError>() {
+                Some(StepError::Timeout { phase }) => assert_eq!(*phase, expected, "{}", path),
+                other => panic!("expected a timeout for {}, got {:?}", path, other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_map_connect_errors_to_the_connect_phase() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let err = reqwest::get(format!("http://{}/", addr)).await.unwrap_err();
+
+        assert_eq!(
+            TimeoutPhase::from_reqwest(&err, false),
+            TimeoutPhase::Connect
+        );
+        assert_eq!(
+            TimeoutPhase::from_reqwest(&err, true),
+            TimeoutPhase::Connect
+        );
+    }
+
+    fn worker_with_failing_steps(server: &TestServer) -> Worker {
+        let mut worker = Worker::new();
+        for (key, path) in [("A", "/ok"), ("B", "/fail"), ("C", "/ok"), ("D", "/fail")] {
+            worker.add_step(FetchStep::named(
+                key,
+                Request::new(Method::GET, server.url(path)),
+            ));
+        }
+        worker
+    }
+
+    #[tokio::test]
+    async fn run_all_should_stop_once_failures_exceed_the_threshold() {
+        let server = TestServer::start(|req| match req.target.as_str() {
+            "/ok" => TestResponse::ok("ok"),
+            _ => TestResponse::status(500),
+        })
+        .await;
+
+        let report = worker_with_failing_steps(&server).run_all(Some(0)).await;
+        let names: Vec<&str> = report.per_step.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names, vec!["A", "B"]);
+        assert_eq!(report.failures, 1);
+
+        let report = worker_with_failing_steps(&server).run_all(Some(1)).await;
+        assert_eq!(report.steps_run, 4);
+        assert_eq!(report.failures, 2);
+    }
+
+    #[tokio::test]
+    async fn run_all_should_run_every_step_without_a_threshold() {
+        let server = TestServer::start(|req| match req.target.as_str() {
+            "/ok" => TestResponse::ok("ok"),
+            _ => TestResponse::status(500),
+        })
+        .await;
+
+        let report = worker_with_failing_steps(&server).run_all(None).await;
+
+        assert_eq!(report.steps_run, 4);
+        assert_eq!(report.successes, 2);
+        assert_eq!(report.failures, 2);
+    }
+
+    #[tokio::test]
+    async fn it_should_skip_on_success_for_unchanged_responses() {
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let server_hits = hits.clone();
+        let server = TestServer::start(move |_| {
+            match server_hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 | 1 => TestResponse::ok("v1"),
+                _ => TestResponse::ok("v2"),
+            }
+        })
+        .await;
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let mut worker = Worker::new();
+        worker.skip_unchanged_responses(true);
+        worker.add_step(RetryStep {
+            url: server.url("/"),
+            retries: 0,
+            calls: calls.clone(),
+        });
+
+        for _ in 0..3 {
+            worker.try_step(RETRY_STEP).await.unwrap();
+        }
+
+        assert_eq!(server.hits(), 3);
+        assert_eq!(calls.lock().unwrap().len(), 2, "the repeated v1 is skipped");
+        assert_eq!(worker.ctx.body_text().unwrap(), "v2");
+    }
+
+    #[tokio::test]
+    async fn it_should_skip_requests_already_seen() {
+        let server = TestServer::start(|req| match req.target.as_str() {
+            "/broken" => TestResponse::status(500),
+            _ => TestResponse::ok("ok"),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.skip_seen_requests(true);
+        worker.add_step(FetchStep::named(
+            "First",
+            Request::new(Method::GET, server.url("/page?a=1&b=2")),
+        ));
+        worker.add_step(FetchStep::named(
+            "Same",
+            Request::new(Method::GET, server.url("/page?b=2&a=1")),
+        ));
+        worker.add_step(FetchStep::named(
+            "Broken",
+            Request::new(Method::GET, server.url("/broken")),
+        ));
+
+        worker.try_step("First").await.unwrap();
+        worker.try_step("Same").await.unwrap();
+        assert_eq!(server.hits(), 1);
+
+        // failed requests aren't remembered
+        assert!(worker.try_step("Broken").await.is_err());
+        assert!(worker.try_step("Broken").await.is_err());
+        assert_eq!(server.hits(), 3);
+
+        worker.reset();
+        worker.try_step("Same").await.unwrap();
+        assert_eq!(server.hits(), 4);
+    }
+
+    #[tokio::test]
+    async fn seen_requests_should_be_remembered_per_session() {
+        let server = TestServer::ok("ok").await;
+
+        let mut worker = Worker::new();
+        worker.skip_seen_requests(true);
+        worker.add_step(FetchStep::new(server.url("/page")));
+        worker.add_step(FetchStep::named(
+            "Other session",
+            Request::new(Method::GET, server.url("/page")).with_session("c".to_string()),
+        ));
+
+        worker.with_session("a").try_step(FETCH_STEP).await.unwrap();
+        worker.try_step(FETCH_STEP).await.unwrap();
+        assert_eq!(server.hits(), 1);
+
+        worker.with_session("b").try_step(FETCH_STEP).await.unwrap();
+        assert_eq!(server.hits(), 2, "session b hasn't seen the request");
+
+        worker.try_step("Other session").await.unwrap();
+        worker.try_step("Other session").await.unwrap();
+        assert_eq!(server.hits(), 3, "nor has the request's own session");
+
+        worker.with_session("a").try_step(FETCH_STEP).await.unwrap();
+        assert_eq!(server.hits(), 3);
+    }
+
+    #[tokio::test]
+    async fn sessions_should_have_isolated_cookies() {
+        let server = TestServer::start(|req| match req.header("Cookie") {
+            Some(cookie) => TestResponse::ok(format!("seen {}", cookie)),
+            None => match req.target.strip_prefix("/login/") {
+                Some(tenant) => TestResponse::ok("welcome")
+                    .header("Set-Cookie", &format!("tenant={}; Path=/", tenant)),
+                None => TestResponse::ok("anonymous"),
+            },
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        for tenant in ["a", "b"] {
+            worker.add_step(FetchStep::named(
+                &format!("Login {}", tenant),
+                Request::new(Method::GET, server.url(&format!("/login/{}", tenant))),
+            ));
+        }
+        worker.add_step(FetchStep::named(
+            "Home",
+            Request::new(Method::GET, server.url("/home")),
+        ));
+
+        worker.with_session("a").try_step("Login a").await.unwrap();
+        worker.try_step("Home").await.unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), "seen tenant=a");
+
+        worker.with_session("b").try_step("Home").await.unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), "anonymous");
+        worker.try_step("Login b").await.unwrap();
+        worker.try_step("Home").await.unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), "seen tenant=b");
+
+        worker.with_session("a").try_step("Home").await.unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), "seen tenant=a");
+
+        worker
+            .with_session("default")
+            .try_step("Home")
+            .await
+            .unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), "anonymous");
+    }
+
+    #[tokio::test]
+    async fn a_request_should_override_the_session_of_the_worker() {
+        let server = TestServer::start(|req| match req.header("Cookie") {
+            Some(cookie) => TestResponse::ok(format!("seen {}", cookie)),
+            None => {
+                let tenant = req.target.trim_start_matches("/login/");
+                TestResponse::ok("welcome")
+                    .header("Set-Cookie", &format!("tenant={}; Path=/", tenant))
+            }
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::named(
+            "Login A",
+            Request::new(Method::GET, server.url("/login/A")),
+        ));
+        worker.add_step(FetchStep::named(
+            "Login B",
+            Request::new(Method::GET, server.url("/login/B")).with_session("B".to_string()),
+        ));
+        worker.add_step(FetchStep::named(
+            "Home",
+            Request::new(Method::GET, server.url("/home")),
+        ));
+        worker.add_step(FetchStep::named(
+            "Home as B",
+            Request::new(Method::GET, server.url("/home")).with_session("B".to_string()),
+        ));
+
+        worker.with_session("A").try_step("Login A").await.unwrap();
+        worker.try_step("Login B").await.unwrap();
+
+        worker.try_step("Home as B").await.unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), "seen tenant=B");
+        worker.try_step("Home").await.unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), "seen tenant=A");
+
+        let requests = server.requests();
+        assert_eq!(requests[1].header("Cookie"), None, "B starts empty");
+    }
+
+    #[tokio::test]
+    async fn it_should_expose_the_context_through_accessors() {
+        let server = TestServer::ok("hello").await;
+
+        let mut worker = Worker::new();
+        worker.context_mut().set_state("page", 2u32);
+        worker.add_step(FetchStep::new(server.url("/")));
+        worker.try_step(FETCH_STEP).await.unwrap();
+
+        let ctx = worker.context();
+        assert_eq!(ctx.get_status_code(), Some(200));
+        assert_eq!(ctx.body_text().unwrap(), "hello");
+        assert_eq!(ctx.get_state::<u32>("page"), Some(&2));
+    }
+
+    #[tokio::test]
+    async fn it_should_transform_the_headers_of_every_request() {
+        let server = TestServer::ok("ok").await;
+
+        let mut worker = Worker::new();
+        worker.set_header_transform(Arc::new(|headers| {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis();
+            headers.insert("X-Timestamp", now.to_string().parse().unwrap());
+        }));
+        worker.add_step(FetchStep::new(server.url("/first")).then("Second"));
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("X-Step", "2".parse().unwrap());
+        worker.add_step(FetchStep::named(
+            "Second",
+            Request::new(Method::GET, server.url("/second")).with_headers(headers),
+        ));
+
+        let report = worker.run(FETCH_STEP).await;
+        assert_eq!(report.successes, 2);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        for req in &requests {
+            let timestamp = req.header("X-Timestamp").expect("timestamp header");
+            assert!(timestamp.parse::<u128>().unwrap() > 0);
+        }
+        assert_eq!(requests[1].header("X-Step"), Some("2"));
+
+        let mut fork = worker.fork();
+        fork.try_step(FETCH_STEP).await.unwrap();
+        assert!(server.requests()[2].header("X-Timestamp").is_some());
+    }
+
+    #[tokio::test]
+    async fn warm_connections_should_respect_the_concurrency_limits() {
+        let server =
+            TestServer::start(|_| TestResponse::ok("").delay(Duration::from_millis(100))).await;
+        let urls: Vec<String> = (0..3).map(|i| server.url(&format!("/{}", i))).collect();
+        let urls: Vec<&str> = urls.iter().map(String::as_str).collect();
+
+        let worker = Worker::new().with_concurrency_limit(1);
+        worker.warm_connections(&urls).await.unwrap();
+        assert_eq!(server.max_in_flight(), 1);
+
+        let mut worker = Worker::new();
+        worker.set_per_host_concurrency(2);
+        worker.warm_connections(&urls).await.unwrap();
+        assert_eq!(server.max_in_flight(), 2);
+    }
+
+    #[tokio::test]
+    async fn warm_connections_should_be_reused_by_the_steps() {
+        let server = TestServer::ok("ok").await;
+        let other = TestServer::ok("ok").await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::new(server.url("/")));
+        worker
+            .warm_connections(&[&server.url("/"), &other.url("/")])
+            .await
+            .unwrap();
+
+        assert_eq!(server.connections(), 1);
+        assert_eq!(server.requests()[0].method, "HEAD");
+        assert_eq!(other.hits(), 1);
+
+        worker.try_step(FETCH_STEP).await.unwrap();
+        worker.try_step(FETCH_STEP).await.unwrap();
+        assert_eq!(server.hits(), 3);
+        assert_eq!(server.connections(), 1, "the warm connection is reused");
+
+        let err = worker.warm_connections(&["http://127.0.0.1:1/"]).await;
+        assert!(matches!(err, Err(StepError::ReqwestError(_))));
+    }
+
+    struct ProxiedStep {
+        proxy: String,
+        request: Request,
+    }
+
+    impl Stepable for ProxiedStep {
+        fn name(&self) -> String {
+            "Proxied".to_string()
+        }
+
+        fn on_request(&self) -> Request {
+            self.request.clone()
+        }
+
+        fn proxy(&self) -> Option<reqwest::Proxy> {
+            Some(reqwest::Proxy::all(&self.proxy).unwrap())
+        }
+
+        fn on_success(&self, _ctx: &mut Context) {}
+
+        fn on_error(&self, _ctx: &mut Context, _err: StepError) {}
+
+        fn on_timeout(&self, _ctx: &mut Context) {}
+    }
+
+    #[tokio::test]
+    async fn it_should_send_requests_through_the_proxy_of_the_step() {
+        let proxy = TestServer::ok("from the step's proxy").await;
+        let own_proxy = TestServer::ok("from the request's proxy").await;
+
+        let mut worker = Worker::new();
+        worker.add_step(ProxiedStep {
+            proxy: proxy.url("/"),
+            request: Request::new(Method::GET, "http://api.invalid/items".to_string()),
+        });
+        worker.try_step("Proxied").await.unwrap();
+
+        assert_eq!(worker.ctx.body_text().unwrap(), "from the step's proxy");
+        assert_eq!(proxy.requests()[0].target, "http://api.invalid/items");
+
+        let mut worker = Worker::new();
+        worker.add_step(ProxiedStep {
+            proxy: proxy.url("/"),
+            request: Request::new(Method::GET, "http://api.invalid/items".to_string())
+                .with_proxy(reqwest::Proxy::all(own_proxy.url("/")).unwrap()),
+        });
+        worker.try_step("Proxied").await.unwrap();
+
+        assert_eq!(worker.ctx.body_text().unwrap(), "from the request's proxy");
+        assert_eq!(proxy.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn proxies_differing_only_by_credentials_should_not_share_a_client() {
+        let proxy = TestServer::ok("proxied").await;
+        let with_user = |user: &str| proxy.url("/").replacen("://", &format!("://{}@", user), 1);
+
+        let mut worker = Worker::new();
+        worker
+            .ctx
+            .client_settings_mut()
+            .add_rotating_proxy(&with_user("alice:one"))
+            .unwrap()
+            .add_rotating_proxy(&with_user("bob:two"))
+            .unwrap();
+        worker.add_step(FetchStep::new("http://api.invalid/".to_string()));
+        for name in ["Alice", "Bob"] {
+            let user = name.to_lowercase();
+            worker.add_step(FetchStep::named(
+                name,
+                Request::new(Method::GET, "http://api.invalid/".to_string()).with_proxy(
+                    reqwest::Proxy::all(proxy.url("/"))
+                        .unwrap()
+                        .basic_auth(&user, "secret"),
+                ),
+            ));
+        }
+
+        for name in [FETCH_STEP, FETCH_STEP, "Alice", "Bob"] {
+            worker.try_step(name).await.unwrap();
+        }
+
+        let auth: Vec<Option<String>> = proxy
+            .requests()
+            .iter()
+            .map(|req| req.header("Proxy-Authorization").map(str::to_string))
+            .collect();
+        assert!(auth.iter().all(Option::is_some), "{:?}", auth);
+        assert_ne!(auth[0], auth[1], "rotating proxies");
+        assert_ne!(auth[2], auth[3], "proxies with basic auth");
+    }
+
+    #[cfg(all(feature = "json", feature = "zstd"))]
+    #[tokio::test]
+    async fn stream_ndjson_should_not_ask_for_zstd() {
+        let server = TestServer::ok("{\"id\": 1}\n").await;
+
+        let mut worker = Worker::new();
+        worker.ctx.client_settings_mut().set_zstd(true);
+        worker.add_step(FetchStep::new(server.url("/items")));
+
+        let mut items = Vec::new();
+        worker
+            .stream_ndjson(FETCH_STEP, |item: serde_json::Value| {
+                items.push(item);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(items, vec![serde_json::json!({"id": 1})]);
+        assert_eq!(server.requests()[0].header("Accept-Encoding"), None);
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn stream_ndjson_should_parse_lines_split_across_chunks() {
+        let server = TestServer::start(|req| match req.target.as_str() {
+            "/bad" => TestResponse::ok("{\"id\": 1}\nnot json\n{\"id\": 3}\n"),
+            _ => TestResponse::ok("").chunked(vec![
+                b"{\"id\": 1}\n{\"id\"".to_vec(),
+                b": 2, \"name\": \"t".to_vec(),
+                b"wo\"}\r\n\n{".to_vec(),
+                b"\"id\": 3}".to_vec(),
+            ]),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::new(server.url("/items")));
+        worker.add_step(FetchStep::named(
+            "Bad",
+            Request::new(Method::GET, server.url("/bad")),
+        ));
+
+        let mut items = Vec::new();
+        worker
+            .stream_ndjson(FETCH_STEP, |item: serde_json::Value| {
+                items.push(item);
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            items,
+            vec![
+                serde_json::json!({"id": 1}),
+                serde_json::json!({"id": 2, "name": "two"}),
+                serde_json::json!({"id": 3}),
+            ]
+        );
+        assert_eq!(worker.ctx.get_status_code(), Some(200));
+        assert_eq!(server.requests()[0].header("Accept-Encoding"), None);
+
+        let mut seen = 0;
+        let err = worker
+            .stream_ndjson("Bad", |_: serde_json::Value| {
+                seen += 1;
+                Ok(())
+            })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Invalid NDJSON line"));
+        assert_eq!(seen, 1, "the stream stops at the invalid line");
+
+        let err = worker
+            .stream_ndjson(FETCH_STEP, |_: serde_json::Value| {
+                Err(StepError::Other("enough".to_string()))
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "enough");
+    }
+
+    #[cfg(feature = "sse")]
+    #[tokio::test]
+    async fn stream_sse_should_dispatch_every_event() {
+        let server = TestServer::start(|_| {
+            TestResponse::ok("")
+                .header("Content-Type", "text/event-stream")
+                .chunked(vec![
+                    b": connected\n\nevent: price\nid: 1\nda".to_vec(),
+                    b"ta: {\"btc\": 1}\n\n".to_vec(),
+                    b"data: multi\r\ndata: line\r\n\r\n".to_vec(),
+                    b"data: incomplete".to_vec(),
+                ])
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::with_request(
+            Request::new(Method::GET, server.url("/feed")).expect_content_type("text/event-stream"),
+        ));
+
+        let mut events = Vec::new();
+        worker
+            .stream_sse(FETCH_STEP, |event| events.push(event))
+            .await
+            .unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event, "price");
+        assert_eq!(events[0].data, "{\"btc\": 1}");
+        assert_eq!(events[0].id.as_deref(), Some("1"));
+        assert_eq!(events[1].event, "message");
+        assert_eq!(events[1].data, "multi\nline");
+        assert_eq!(events[1].id.as_deref(), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn reset_should_clear_runtime_state_but_keep_steps() {
+        let server =
+            TestServer::start(|_| TestResponse::ok("ok").header("Set-Cookie", "session=abc")).await;
+
+        let mut worker = Worker::new();
+        worker
+            .ctx
+            .client_settings_mut()
+            .set_max_decompressed_bytes(Some(1024));
+        worker.add_step(FetchStep::new(server.url("/")));
+        worker.ctx.set_state("page", 3u32);
+
+        let report = worker.run(FETCH_STEP).await;
+        assert_eq!(report.successes, 1);
+        assert_eq!(worker.stats().runs, 1);
+
+        worker.reset();
+
+        assert_eq!(worker.stats(), crate::RunStats::default());
+        assert!(worker.ctx.state_keys().is_empty());
+        assert!(worker.ctx.body_bytes().is_err());
+        assert_eq!(
+            worker.ctx.client_settings().max_decompressed_bytes(),
+            Some(1024)
+        );
+
+        worker.try_step(FETCH_STEP).await.unwrap();
+        assert_eq!(worker.stats().runs, 1);
+        assert_eq!(
+            server.requests()[1].header("Cookie"),
+            None,
+            "cookies from before the reset are dropped"
+        );
+
+        let dead_proxy = "http://127.0.0.1:1";
+        let settings = worker.ctx.client_settings_mut();
+        settings
+            .add_rotating_proxy(dead_proxy)
+            .unwrap()
+            .set_proxy_check_url(&server.url("/"));
+        settings.check_proxies().await;
+        assert_eq!(settings.proxy_failures(dead_proxy), Some(1));
+        assert!(settings.active_proxies().is_empty());
+
+        worker.reset();
+
+        let settings = worker.ctx.client_settings();
+        assert_eq!(settings.proxy_failures(dead_proxy), Some(0));
+        assert_eq!(settings.active_proxies(), vec![dead_proxy.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn reset_should_keep_an_injected_client() {
+        let server =
+            TestServer::start(|_| TestResponse::ok("ok").header("Set-Cookie", "session=abc")).await;
+
+        let cookie_store = crate::http_requester::new_cookie_store();
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert("X-Custom-Client", "yes".parse().unwrap());
+        let client = reqwest::Client::builder()
+            .cookie_provider(cookie_store.clone())
+            .default_headers(default_headers)
+            .build()
+            .unwrap();
+
+        let mut worker = Worker::new();
+        worker
+            .ctx
+            .set_http_requester(crate::HttpRequester::from_client(client, cookie_store));
+        worker.add_step(FetchStep::new(server.url("/")));
+        worker.with_session("other");
+        worker.try_step(FETCH_STEP).await.unwrap();
+
+        worker.reset();
+        worker.try_step(FETCH_STEP).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(
+            requests[1].header("X-Custom-Client"),
+            Some("yes"),
+            "the injected client should still send the requests"
+        );
+        assert_eq!(requests[1].header("Cookie"), None);
+    }
+
+    // every (downloaded, total) pair reported to `on_progress`
+    type Progress = Arc<std::sync::Mutex<Vec<(u64, Option<u64>)>>>;
+
+    struct ProgressStep {
+        url: String,
+        progress: Progress,
+    }
+
+    impl Stepable for ProgressStep {
+        fn name(&self) -> String {
+            String::from("ProgressStep")
+        }
+
+        fn on_request(&self) -> Request {
+            Request::new(Method::GET, self.url.clone())
+        }
+
+        fn on_success(&self, _ctx: &mut Context) {}
+
+        fn on_timeout(&self, _ctx: &mut Context) {}
+
+        fn on_progress(&self, downloaded: u64, total: Option<u64>) {
+            self.progress.lock().unwrap().push((downloaded, total));
+        }
+    }
+
+    #[tokio::test]
+    async fn on_progress_should_report_increasing_downloaded_bytes() {
+        let body = vec![b'x'; 5 * PROGRESS_INTERVAL as usize + 100];
+        let len = body.len() as u64;
+        let server = TestServer::start(move |_| TestResponse::ok(body.clone())).await;
+        let progress = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let mut worker = Worker::new();
+        worker.add_step(ProgressStep {
+            url: server.url("/"),
+            progress: progress.clone(),
+        });
+        worker.try_step("ProgressStep").await.unwrap();
+
+        let progress = progress.lock().unwrap();
+        assert!(progress.len() > 1, "{:?}", progress);
+        assert!(progress.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(progress.iter().all(|(_, total)| *total == Some(len)));
+        assert_eq!(progress.last().unwrap().0, len);
+    }
+
+    #[tokio::test]
+    async fn cache_responses_should_honor_cache_control() {
+        let server = TestServer::start(|req| {
+            let cache_control = match req.target.as_str() {
+                "/fresh" => "max-age=60",
+                "/stale" => "max-age=0",
+                _ => "no-store",
+            };
+            TestResponse::ok(req.target.clone()).header("Cache-Control", cache_control)
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.cache_responses(true);
+        for path in ["/fresh", "/stale", "/private"] {
+            worker.add_step(FetchStep::named(
+                path,
+                Request::new(Method::GET, server.url(path)),
+            ));
+        }
+
+        for _ in 0..2 {
+            for path in ["/fresh", "/stale", "/private"] {
+                worker.try_step(path).await.unwrap();
+                assert_eq!(worker.ctx.body_text().unwrap(), path);
+                assert_eq!(worker.ctx.get_status_code(), Some(200));
+            }
+        }
+
+        let targets: Vec<String> = server.requests().into_iter().map(|r| r.target).collect();
+        assert_eq!(
+            targets,
+            ["/fresh", "/stale", "/private", "/stale", "/private"],
+            "only the response with a positive max-age is served from the cache"
+        );
+    }
+
+    #[tokio::test]
+    async fn cache_responses_should_not_share_responses_between_sessions() {
+        let server = TestServer::start(|req| match req.target.as_str() {
+            "/login" => TestResponse::ok("welcome")
+                .header("Set-Cookie", "user=a; Path=/")
+                .header("Cache-Control", "no-store"),
+            _ => TestResponse::ok(req.header("Cookie").unwrap_or("anonymous").to_string())
+                .header("Cache-Control", "max-age=60"),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.cache_responses(true);
+        worker.add_step(FetchStep::named(
+            "Login",
+            Request::new(Method::GET, server.url("/login")),
+        ));
+        worker.add_step(FetchStep::named(
+            "Me",
+            Request::new(Method::GET, server.url("/me")),
+        ));
+
+        worker.with_session("a").try_step("Login").await.unwrap();
+        worker.try_step("Me").await.unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), "user=a");
+
+        worker.with_session("b").try_step("Me").await.unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), "anonymous");
+
+        worker.with_session("a").try_step("Me").await.unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), "user=a");
+        assert_eq!(
+            server.hits(),
+            3,
+            "only session a's second request is cached"
+        );
+    }
+
+    #[tokio::test]
+    async fn cache_responses_should_not_keep_error_responses() {
+        let server = TestServer::start(|_| {
+            TestResponse::status(500)
+                .body("oops")
+                .header("Cache-Control", "max-age=60")
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.cache_responses(true);
+        worker.add_step(FetchStep::with_request(
+            Request::new(Method::GET, server.url("/")).with_status_codes(vec![500]),
+        ));
+
+        worker.try_step(FETCH_STEP).await.unwrap();
+        worker.try_step(FETCH_STEP).await.unwrap();
+        assert_eq!(server.hits(), 2);
+    }
+
+    struct TokenStep {
+        url: String,
+        token: Arc<tokio::sync::Mutex<String>>,
+    }
+
+    #[async_trait]
+    impl Stepable for TokenStep {
+        fn name(&self) -> String {
+            String::from("TokenStep")
+        }
+
+        fn on_request(&self) -> Request {
+            unreachable!("the worker builds requests with on_request_async")
+        }
+
+        async fn on_request_async(&self, _ctx: &Context) -> Request {
+            let token = self.token.lock().await.clone();
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "Authorization",
+                format!("Bearer {}", token).parse().unwrap(),
+            );
+            Request::new(Method::GET, self.url.clone()).with_headers(headers)
+        }
+
+        fn on_success(&self, _ctx: &mut Context) {}
+
+        fn on_timeout(&self, _ctx: &mut Context) {}
+    }
+
+    #[tokio::test]
+    async fn on_request_async_should_build_the_request() {
+        let server = TestServer::ok("ok").await;
+        let token = Arc::new(tokio::sync::Mutex::new(String::from("first")));
+
+        let mut worker = Worker::new();
+        worker.add_step(TokenStep {
+            url: server.url("/"),
+            token: token.clone(),
+        });
+        worker.try_step("TokenStep").await.unwrap();
+        *token.lock().await = String::from("second");
+        worker.try_step("TokenStep").await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("Authorization"), Some("Bearer first"));
+        assert_eq!(requests[1].header("Authorization"), Some("Bearer second"));
+    }
+
+    struct DependentStep {
+        name: &'static str,
+        depends_on: Vec<&'static str>,
+    }
+
+    impl Stepable for DependentStep {
+        fn name(&self) -> String {
+            self.name.to_string()
+        }
+
+        fn depends_on(&self) -> Vec<String> {
+            self.depends_on.iter().map(|dep| dep.to_string()).collect()
+        }
+
+        fn on_request(&self) -> Request {
+            unreachable!("describe doesn't build requests")
+        }
+
+        fn on_success(&self, _ctx: &mut Context) {}
+
+        fn on_timeout(&self, _ctx: &mut Context) {}
     }
 
     #[test]
-    fn it_should_skip_to_step() {
+    fn describe_should_draw_every_step_and_dependency() {
         let mut worker = Worker::new();
-        worker.add_step(SkippableStep);
+        for (name, depends_on) in [
+            ("Login", vec!["Search"]),
+            ("Search", vec!["Login", "Warmup"]),
+            ("Detail \"page\"", vec!["Search"]),
+        ] {
+            worker.add_step(DependentStep { name, depends_on });
+        }
 
-        let step = worker.get_step(SKIPPABLE_STEP).unwrap();
-        let req = step.on_request();
+        assert_eq!(
+            worker.describe(),
+            "graph TD\n\
+            \x20   s0[\"Detail #quot;page#quot;\"]\n\
+            \x20   s1[\"Login\"]\n\
+            \x20   s2[\"Search\"]\n\
+            \x20   s3[\"Warmup\"]\n\
+            \x20   s2 --> s0\n\
+            \x20   s2 --> s1\n\
+            \x20   s1 --> s2\n\
+            \x20   s3 --> s2\n\
+            \x20   style s3 stroke-dasharray: 5 5\n"
+        );
+    }
 
-        assert_eq!(req.get_skip_to_step().unwrap(), ROBOTS_TXT);
+    #[tokio::test]
+    async fn run_where_should_only_run_matching_steps() {
+        let server = TestServer::start(|req| match req.target.as_str() {
+            "/api/broken" => TestResponse::status(500),
+            _ => TestResponse::ok("ok"),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        for path in ["/api/users", "/api/broken", "/web/home"] {
+            worker.add_step(FetchStep::named(
+                path,
+                Request::new(Method::GET, server.url(path)),
+            ));
+        }
+
+        let results = worker
+            .run_where(|step| step.name().starts_with("/api/"))
+            .await;
+
+        let keys: Vec<&str> = results.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["/api/broken", "/api/users"]);
+        assert!(matches!(
+            results[0].1,
+            Err(StepError::StatusCodeNotFound(500, _))
+        ));
+        assert!(results[1].1.is_ok());
+        assert_eq!(server.hits(), 2);
+    }
+
+    struct LabeledStep {
+        url: String,
+    }
+
+    #[async_trait]
+    impl Stepable for LabeledStep {
+        fn name(&self) -> String {
+            String::from("Labeled")
+        }
+
+        fn metric_labels(&self) -> Vec<(String, String)> {
+            vec![
+                ("group".to_string(), "search".to_string()),
+                ("region".to_string(), "eu".to_string()),
+            ]
+        }
+
+        fn on_request(&self) -> Request {
+            Request::new(Method::GET, self.url.clone())
+        }
+
+        fn on_success(&self, ctx: &mut Context) {
+            ctx.set_next_step(FETCH_STEP.to_string());
+        }
+
+        fn on_timeout(&self, _ctx: &mut Context) {}
+    }
+
+    type Labels = Vec<(String, String)>;
+
+    #[derive(Default)]
+    struct RecordingMetrics {
+        steps: std::sync::Mutex<Vec<(String, bool, Labels)>>,
+    }
+
+    impl Metrics for RecordingMetrics {
+        fn record_step(&self, outcome: &StepOutcome, labels: &[(String, String)]) {
+            self.steps.lock().unwrap().push((
+                outcome.name.clone(),
+                outcome.is_success(),
+                labels.to_vec(),
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn metrics_should_receive_each_step_with_its_labels() {
+        let server = TestServer::start(|req| match req.target.as_str() {
+            "/search" => TestResponse::ok("ok"),
+            _ => TestResponse::status(500),
+        })
+        .await;
+        let metrics = Arc::new(RecordingMetrics::default());
+
+        let mut worker = Worker::new();
+        worker.set_metrics(metrics.clone());
+        worker.add_step(LabeledStep {
+            url: server.url("/search"),
+        });
+        worker.add_step(FetchStep::new(server.url("/broken")));
+
+        worker.run("Labeled").await;
+
+        let steps = metrics.steps.lock().unwrap();
+        let labels = vec![
+            ("group".to_string(), "search".to_string()),
+            ("region".to_string(), "eu".to_string()),
+        ];
+        assert_eq!(
+            *steps,
+            vec![
+                ("Labeled".to_string(), true, labels),
+                (FETCH_STEP.to_string(), false, Vec::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn has_step_should_tell_registered_steps_apart() {
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::named("Home", Request::default()));
+        let worker = worker;
+
+        assert!(worker.has_step("Home"));
+        assert!(!worker.has_step("Missing"));
+    }
+
+    #[tokio::test]
+    async fn run_collect_should_return_the_bodies_of_the_successful_steps() {
+        let server = TestServer::start(|req| match req.target.as_str() {
+            "/users" => TestResponse::ok("[1, 2]"),
+            "/orders" => TestResponse::ok("[3]"),
+            _ => TestResponse::status(500),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        for path in ["/users", "/orders", "/broken"] {
+            worker.add_step(FetchStep::named(
+                path,
+                Request::new(Method::GET, server.url(path)),
+            ));
+        }
+
+        let bodies = worker.run_collect(&["/users", "/orders", "/broken"]).await;
+
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies["/users"], Bytes::from_static(b"[1, 2]"));
+        assert_eq!(bodies["/orders"], Bytes::from_static(b"[3]"));
+        assert_eq!(server.hits(), 3);
+    }
+
+    #[tokio::test]
+    async fn run_should_call_warmup_before_and_teardown_after_the_steps() {
+        let server = TestServer::ok("ok").await;
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let mut worker = Worker::new();
+        worker.add_step(
+            FetchStep::named("First", Request::new(Method::GET, server.url("/"))).then("Second"),
+        );
+        worker.add_step(FetchStep::named(
+            "Second",
+            Request::new(Method::GET, server.url("/")),
+        ));
+        let warmup_calls = calls.clone();
+        worker.set_warmup(Arc::new(move |ctx: &mut Context| {
+            assert_eq!(ctx.get_current_step(), None, "no step ran yet");
+            warmup_calls.lock().unwrap().push(String::from("warmup"));
+            ctx.set_state("token", String::from("t0k3n"));
+            Ok(())
+        }));
+        let teardown_calls = calls.clone();
+        worker.set_teardown(Arc::new(move |ctx: &mut Context| {
+            let token = ctx
+                .get_state::<String>("token")
+                .cloned()
+                .unwrap_or_default();
+            let last = ctx.get_current_step().unwrap();
+            teardown_calls
+                .lock()
+                .unwrap()
+                .push(format!("teardown after {} with {}", last, token));
+            Ok(())
+        }));
+
+        let report = worker.run("First").await;
+
+        assert_eq!(report.steps_run, 2);
+        assert_eq!(report.successes, 2);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["warmup", "teardown after Second with t0k3n"]
+        );
+    }
+
+    #[tokio::test]
+    async fn run_should_skip_the_steps_when_warmup_fails() {
+        let server = TestServer::ok("ok").await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::new(server.url("/")));
+        worker.set_warmup(Arc::new(|_: &mut Context| {
+            Err(StepError::Other(String::from("no token")))
+        }));
+        worker.set_teardown(Arc::new(|_: &mut Context| {
+            panic!("teardown shouldn't run without a warmup")
+        }));
+
+        let report = worker.run(FETCH_STEP).await;
+
+        assert_eq!(report.failures, 1);
+        assert_eq!(report.per_step[0].name, "warmup");
+        assert_eq!(server.hits(), 0);
+    }
+
+    struct EnvelopeStep {
+        url: String,
+    }
+
+    impl Stepable for EnvelopeStep {
+        fn name(&self) -> String {
+            String::from("EnvelopeStep")
+        }
+
+        fn on_request(&self) -> Request {
+            Request::new(Method::GET, self.url.clone())
+        }
+
+        fn transform_body(&self, bytes: Bytes) -> Bytes {
+            bytes.slice(4.min(bytes.len())..)
+        }
+
+        fn on_success(&self, _ctx: &mut Context) {}
+
+        fn on_timeout(&self, _ctx: &mut Context) {}
+    }
+
+    #[tokio::test]
+    async fn transform_body_should_rewrite_the_body_of_its_step_only() {
+        let server = TestServer::ok("\0\0\0\x05hello").await;
+
+        let mut worker = Worker::new();
+        worker.add_step(EnvelopeStep {
+            url: server.url("/"),
+        });
+        worker.add_step(FetchStep::new(server.url("/")));
+
+        worker.try_step("EnvelopeStep").await.unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), "hello");
+
+        worker.try_step(FETCH_STEP).await.unwrap();
+        assert_eq!(worker.ctx.body_text().unwrap(), "\0\0\0\x05hello");
+    }
+
+    #[tokio::test]
+    async fn run_until_should_poll_until_the_predicate_holds() {
+        let polls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = polls.clone();
+        let server = TestServer::start(move |_| {
+            match counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 | 1 => TestResponse::ok("pending"),
+                _ => TestResponse::ok("done"),
+            }
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::new(server.url("/")));
+
+        let is_done = |ctx: &Context| ctx.body_text().is_ok_and(|body| body == "done");
+        let ctx = worker
+            .run_until(FETCH_STEP, is_done, Duration::from_millis(10), 10)
+            .await;
+
+        assert_eq!(ctx.body_text().unwrap(), "done");
+        assert_eq!(server.hits(), 3);
+
+        let ctx = worker
+            .run_until(FETCH_STEP, |_| false, Duration::from_millis(1), 2)
+            .await;
+        assert_eq!(ctx.get_status_code(), Some(200));
+        assert_eq!(server.hits(), 5, "gives up after max_iters runs");
+    }
+
+    #[tokio::test]
+    async fn last_error_should_record_the_error_of_the_last_failed_step() {
+        let server = TestServer::start(|req| match req.target.as_str() {
+            "/missing" => TestResponse::status(404),
+            _ => TestResponse::ok("ok"),
+        })
+        .await;
+
+        let mut worker = Worker::new();
+        worker.add_step(FetchStep::named(
+            "Missing",
+            Request::new(Method::GET, server.url("/missing")),
+        ));
+        worker.add_step(FetchStep::named(
+            "Found",
+            Request::new(Method::GET, server.url("/")),
+        ));
+        assert!(worker.ctx.last_error().is_none());
+
+        worker.try_step("Missing").await.unwrap_err();
+        worker.try_step("Found").await.unwrap();
+
+        assert!(matches!(
+            worker.ctx.last_error(),
+            Some(StepError::StatusCodeNotFound(404, _))
+        ));
+    }
+
+    struct FlowStep {
+        url: String,
+        action: FlowControl,
+    }
+
+    impl Stepable for FlowStep {
+        fn name(&self) -> String {
+            String::from("FlowStep")
+        }
+
+        fn on_request(&self) -> Request {
+            Request::new(Method::GET, self.url.clone())
+        }
+
+        fn on_success(&self, ctx: &mut Context) {
+            ctx.set_next_step(String::from("Next"));
+        }
+
+        fn on_success_action(&self, _ctx: &mut Context) -> FlowControl {
+            self.action.clone()
+        }
+
+        fn on_timeout(&self, _ctx: &mut Context) {}
+    }
+
+    #[tokio::test]
+    async fn on_success_action_should_decide_the_next_step() {
+        let server = TestServer::ok("ok").await;
+
+        for (action, expected) in [
+            (FlowControl::Continue, vec!["FlowStep", "Next"]),
+            (FlowControl::Halt, vec!["FlowStep"]),
+            (
+                FlowControl::GoTo(String::from("Other")),
+                vec!["FlowStep", "Other"],
+            ),
+        ] {
+            let mut worker = Worker::new();
+            worker.add_step(FlowStep {
+                url: server.url("/"),
+                action: action.clone(),
+            });
+            worker.add_step(FetchStep::named(
+                "Next",
+                Request::new(Method::GET, server.url("/")),
+            ));
+            worker.add_step(FetchStep::named(
+                "Other",
+                Request::new(Method::GET, server.url("/")),
+            ));
+
+            let report = worker.run("FlowStep").await;
+
+            let steps: Vec<&str> = report.per_step.iter().map(|s| s.name.as_str()).collect();
+            assert_eq!(steps, expected, "{:?}", action);
+            assert_eq!(report.failures, 0);
+        }
     }
 }